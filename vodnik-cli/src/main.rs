@@ -5,9 +5,9 @@ use std::{
 };
 
 use clap::{Parser, Subcommand, ValueEnum};
-use reqwest::Client;
+use reqwest::{Client, header::CONTENT_TYPE};
 use vodnik_core::{
-    api::{BatchIngest, ValueVec},
+    api::{BINARY_BATCH_CONTENT_TYPE, BatchIngest, ValueVec},
     codec,
     meta::{BlockMeta, Quality, SeriesId, SizedBlock, StorableNum},
     wal::{TAG_WRITE, WalEntryHeader, WalFrameIterator},
@@ -53,6 +53,10 @@ enum Commands {
         /// Quality flag to apply to all points
         #[arg(long, default_value_t = 192)]
         quality: u8,
+
+        /// Send the batch in the binary encoding instead of JSON
+        #[arg(long)]
+        binary: bool,
     },
 
     /// inspect a local block file
@@ -113,7 +117,13 @@ async fn main() -> anyhow::Result<()> {
             start,
             quality,
             stype,
-        } => generate_data(&cli, series_id, count, pattern, start, quality, stype).await?,
+            binary,
+        } => {
+            generate_data(
+                &cli, series_id, count, pattern, start, quality, stype, binary,
+            )
+            .await?
+        }
         Commands::InspectBlock { path, head } => inspect_block(path, head)?,
         Commands::InspectWal { path, mode } => inspect_wal(path, mode)?,
     }
@@ -154,6 +164,7 @@ fn print_frame(frame: &mut vodnik_core::wal::WalFrame, mode: WalInspectMode) ->
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn generate_data(
     cli: &Cli,
     series_id: NonZero<u64>,
@@ -162,6 +173,7 @@ async fn generate_data(
     start: Option<u64>,
     quality: u8,
     stype: StorageType,
+    binary: bool,
 ) -> anyhow::Result<()> {
     let start_ts = start.unwrap_or_else(|| {
         let now = SystemTime::now()
@@ -192,6 +204,7 @@ async fn generate_data(
         series: SeriesId(series_id),
         ts: ts,
         qs: qs,
+        batch_id: None,
         vals,
     };
 
@@ -199,7 +212,14 @@ async fn generate_data(
     let target_url = format!("{}/batch", cli.url);
 
     let start = Instant::now();
-    let resp = client.post(&target_url).json(&payload).send().await?;
+    let req = client.post(&target_url);
+    let req = if binary {
+        req.header(CONTENT_TYPE, BINARY_BATCH_CONTENT_TYPE)
+            .body(payload.to_binary())
+    } else {
+        req.json(&payload)
+    };
+    let resp = req.send().await?;
     let duration = start.elapsed();
 
     println!("<-- Status: {} (took {:.2?})", resp.status(), duration);
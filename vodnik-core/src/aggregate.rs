@@ -0,0 +1,480 @@
+use std::ops::Range;
+
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::{local_to_utc_ms, utc_ms_to_local};
+use crate::meta::{BlockMeta, Quality, SafeAdd, SeriesId, StorableNum};
+
+pub mod integral;
+pub mod opc;
+
+// which samples an aggregation takes into account
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityFilter {
+    Good,
+    // good | uncertain, same as the stats in the block meta
+    #[default]
+    Valid,
+    // every non missing sample, bad ones included
+    NonMissing,
+}
+
+impl QualityFilter {
+    pub fn accepts(self, q: Quality) -> bool {
+        match self {
+            QualityFilter::Good => q.is_good(),
+            QualityFilter::Valid => q.is_good() || q.is_uncertain(),
+            QualityFilter::NonMissing => !q.is_missing(),
+        }
+    }
+
+    // the block meta stats cover the valid samples. they can stand in for the other filters,
+    // as long as the block has no samples that the filter would treat differently.
+    pub fn matches_meta<T: StorableNum>(self, meta: &BlockMeta<T>) -> bool {
+        match self {
+            QualityFilter::Good => meta.qual_acc_or & BlockMeta::<T>::ACC_UNCERTAIN == 0,
+            QualityFilter::Valid => true,
+            QualityFilter::NonMissing => meta.qual_acc_or & BlockMeta::<T>::ACC_BAD == 0,
+        }
+    }
+}
+
+// Running aggregation over the samples of a time range that pass the quality filter.
+// Can be fed with raw samples or, for blocks fully inside the range, with the block meta.
+#[derive(Debug, Clone)]
+pub struct RangeAgg<T: StorableNum> {
+    pub filter: QualityFilter,
+    // sample slots seen, including missing ones
+    pub slots: u64,
+    // ACC_* flags of all slots seen, see BlockMeta
+    pub qual_acc_or: u32,
+    pub count_non_missing: u64,
+    // samples passing the filter
+    pub count_valid: u64,
+    pub sum: T::Accumulator,
+    pub min: T,
+    pub max: T,
+    // (ts, value, quality) of the first/last sample passing the filter
+    pub first: Option<(u64, T, Quality)>,
+    pub last: Option<(u64, T, Quality)>,
+}
+
+impl<T: StorableNum> Default for RangeAgg<T> {
+    fn default() -> Self {
+        Self::new(QualityFilter::default())
+    }
+}
+
+impl<T: StorableNum> RangeAgg<T> {
+    pub fn new(filter: QualityFilter) -> Self {
+        Self {
+            filter,
+            slots: 0,
+            qual_acc_or: 0,
+            count_non_missing: 0,
+            count_valid: 0,
+            sum: T::Accumulator::default(),
+            min: T::max_value(),
+            max: T::min_value(),
+            first: None,
+            last: None,
+        }
+    }
+
+    pub fn add_sample(&mut self, ts: u64, v: T, q: Quality) {
+        self.slots += 1;
+        self.qual_acc_or |= BlockMeta::<T>::qual_flag(q);
+
+        if q.is_missing() {
+            return;
+        }
+        self.count_non_missing += 1;
+
+        if !self.filter.accepts(q) {
+            return;
+        }
+
+        self.count_valid += 1;
+        self.sum = self.sum.safe_add(v.to_acc());
+        if v < self.min {
+            self.min = v;
+        }
+        if v > self.max {
+            self.max = v;
+        }
+        self.set_first_last(ts, v, q);
+    }
+
+    // true if the block can be added via `add_block`
+    pub fn can_use_meta(&self, meta: &BlockMeta<T>) -> bool {
+        self.filter.matches_meta(meta)
+    }
+
+    // adds a whole block of `slots` samples via its meta, without touching the samples.
+    // only valid if `can_use_meta` holds for the block.
+    pub fn add_block(&mut self, block_start: u64, sample_ms: u64, slots: u64, meta: &BlockMeta<T>) {
+        debug_assert!(self.can_use_meta(meta));
+
+        self.slots += slots;
+        self.qual_acc_or |= meta.qual_acc_or;
+        self.count_non_missing += meta.count_non_missing as u64;
+
+        if meta.count_valid == 0 {
+            return;
+        }
+
+        self.count_valid += meta.count_valid as u64;
+        self.sum = self.sum.safe_add(meta.sum);
+        if meta.min < self.min {
+            self.min = meta.min;
+        }
+        if meta.max > self.max {
+            self.max = meta.max;
+        }
+
+        self.set_first_last(
+            block_start + meta.fst_valid_offset as u64 * sample_ms,
+            meta.fst_valid,
+            meta.fst_valid_q,
+        );
+        self.set_first_last(
+            block_start + meta.lst_valid_offset as u64 * sample_ms,
+            meta.lst_valid,
+            meta.lst_valid_q,
+        );
+    }
+
+    fn set_first_last(&mut self, ts: u64, v: T, q: Quality) {
+        if self.first.is_none_or(|(t, ..)| ts < t) {
+            self.first = Some((ts, v, q));
+        }
+        if self.last.is_none_or(|(t, ..)| ts >= t) {
+            self.last = Some((ts, v, q));
+        }
+    }
+
+    // quality of the aggregate. `expected_slots` is the number of sample slots in the bucket,
+    // slots not seen at all (no block stored) count as gaps.
+    //  - missing: no data at all
+    //  - bad: there was data, but none of it passed the filter
+    //  - good: every slot holds a good sample
+    //  - uncertain: everything else, i.e. mixed qualities or gaps
+    pub fn quality(&self, expected_slots: u64) -> Quality {
+        if self.count_non_missing == 0 {
+            return Quality::MISSING;
+        }
+        if self.count_valid == 0 {
+            return Quality::BAD;
+        }
+        if self.qual_acc_or == BlockMeta::<T>::ACC_GOOD && self.slots >= expected_slots {
+            return Quality::GOOD;
+        }
+        Quality::UNCERTAIN
+    }
+
+    pub fn result(&self, expected_slots: u64) -> AggResult {
+        let has_valid = self.count_valid > 0;
+        let sum: Option<f64> = if has_valid {
+            num_traits::cast(self.sum)
+        } else {
+            None
+        };
+
+        AggResult {
+            q: self.quality(expected_slots),
+            count: self.count_non_missing,
+            count_valid: self.count_valid,
+            sum,
+            avg: sum.map(|s| s / self.count_valid as f64),
+            min: has_valid.then(|| num_traits::cast(self.min)).flatten(),
+            max: has_valid.then(|| num_traits::cast(self.max)).flatten(),
+            first: self.first.and_then(AggSample::from_tuple),
+            last: self.last.and_then(AggSample::from_tuple),
+        }
+    }
+}
+
+// number of sample slots starting in [from, to), with slots aligned to UNIX EPOCH
+pub fn slots_in_range(from: u64, to: u64, sample_ms: u64) -> u64 {
+    to.div_ceil(sample_ms)
+        .saturating_sub(from.div_ceil(sample_ms))
+}
+
+// Feeds the samples of a block slice into the buckets they belong to.
+// `bounds` holds the bucket boundaries, bucket i covers [bounds[i], bounds[i + 1]).
+// only samples inside `range` are considered.
+pub fn add_slice_bucketed<T: StorableNum>(
+    aggs: &mut [RangeAgg<T>],
+    bounds: &[u64],
+    slice_start: u64,
+    sample_ms: u64,
+    vals: &[T],
+    qs: &[Quality],
+    range: Range<u64>,
+) {
+    debug_assert_eq!(aggs.len() + 1, bounds.len());
+
+    let mut b = bucket_index(bounds, slice_start.max(range.start));
+    for i in 0..qs.len() {
+        let ts = slice_start + i as u64 * sample_ms;
+        if ts < range.start {
+            continue;
+        }
+        if ts >= range.end {
+            break;
+        }
+        while b + 1 < bounds.len() && ts >= bounds[b + 1] {
+            b += 1;
+        }
+        if b < aggs.len() {
+            aggs[b].add_sample(ts, vals[i], qs[i]);
+        }
+    }
+}
+
+// index of the bucket containing ts. ts must be inside [bounds[0], bounds[last])
+pub fn bucket_index(bounds: &[u64], ts: u64) -> usize {
+    bounds.partition_point(|b| *b <= ts).saturating_sub(1)
+}
+
+// boundaries of fixed size buckets aligned to UNIX EPOCH, covering [from, to)
+pub fn fixed_bucket_bounds(from: u64, to: u64, interval_ms: u64) -> Vec<u64> {
+    let mut bounds = vec![];
+    let mut b = from - from % interval_ms;
+    while b < to {
+        bounds.push(b);
+        b += interval_ms;
+    }
+    bounds.push(b);
+    bounds
+}
+
+// boundaries of buckets aligned to the local wall clock of `tz`, covering [from, to).
+// buckets are `interval_ms` long in local time, so daily buckets are 23h/25h long on DST changes.
+// returns the boundaries together with the local start time of each bucket.
+pub fn local_bucket_bounds(
+    from: u64,
+    to: u64,
+    interval_ms: u64,
+    tz: &Tz,
+) -> (Vec<u64>, Vec<NaiveDateTime>) {
+    let mut bounds = vec![];
+    let mut labels = vec![];
+
+    let Some(local_from) = utc_ms_to_local(tz, from as i64) else {
+        return (vec![from, to], vec![NaiveDateTime::default()]);
+    };
+    let local_ms = local_from.and_utc().timestamp_millis();
+    let mut b = local_ms - local_ms.rem_euclid(interval_ms as i64);
+
+    loop {
+        let Some(local) = DateTime::from_timestamp_millis(b).map(|dt| dt.naive_utc()) else {
+            bounds.push(to);
+            break;
+        };
+        let utc = local_to_utc_ms(tz, local).max(0) as u64;
+
+        // local times skipped by a DST gap are shifted forward onto or past the following bounds,
+        // drop them. only the first bound may fall into a gap, it has to cover `from`
+        let skipped = matches!(tz.from_local_datetime(&local), LocalResult::None);
+        if let Some(last) = bounds.last()
+            && (skipped || utc <= *last)
+        {
+            b += interval_ms as i64;
+            continue;
+        }
+        bounds.push(utc);
+
+        if utc >= to {
+            break;
+        }
+        labels.push(local);
+        b += interval_ms as i64;
+    }
+
+    (bounds, labels)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BucketRow {
+    pub start: u64,
+    pub end: u64,
+    // local wall clock start of the bucket, for buckets aligned to the series timezone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>,
+    #[serde(flatten)]
+    pub agg: AggResult,
+}
+
+// one bucket of a multi series query. `agg` combines the bucket over all series
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiBucketRow {
+    // bucket start for UTC aligned buckets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<u64>,
+    // local wall clock start for buckets aligned to the series timezones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>,
+    #[serde(flatten)]
+    pub agg: AggResult,
+    pub series: Vec<SeriesBucketRow>,
+}
+
+// a range aggregated over multiple series. `agg` combines all series
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiAggResult {
+    #[serde(flatten)]
+    pub agg: AggResult,
+    pub series: Vec<SeriesAggResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesAggResult {
+    pub series: SeriesId,
+    #[serde(flatten)]
+    pub agg: AggResult,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesBucketRow {
+    pub series: SeriesId,
+    #[serde(flatten)]
+    pub row: BucketRow,
+}
+
+// values are reported as f64, same as min/max in the block meta store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggResult {
+    // derived quality of the aggregate, see RangeAgg::quality
+    pub q: Quality,
+    pub count: u64,
+    pub count_valid: u64,
+    pub sum: Option<f64>,
+    pub avg: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub first: Option<AggSample>,
+    pub last: Option<AggSample>,
+}
+
+impl AggResult {
+    // combines the results of two disjoint sets of samples, e.g. the same bucket of two series
+    pub fn merge(&mut self, other: &AggResult) {
+        // a gap or mix in one of the parts makes the whole uncertain
+        let (a, b) = (self.q, other.q);
+        self.q = if a.is_missing() && b.is_missing() {
+            Quality::MISSING
+        } else if a.is_good() && b.is_good() {
+            Quality::GOOD
+        } else if a.is_bad() && b.is_bad() {
+            Quality::BAD
+        } else {
+            Quality::UNCERTAIN
+        };
+        self.count += other.count;
+        self.count_valid += other.count_valid;
+
+        self.sum = match (self.sum, other.sum) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        self.avg = self.sum.map(|s| s / self.count_valid as f64);
+        self.min = match (self.min, other.min) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max = match (self.max, other.max) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+
+        if let Some(o) = &other.first
+            && self.first.as_ref().is_none_or(|s| o.ts < s.ts)
+        {
+            self.first = Some(o.clone());
+        }
+        if let Some(o) = &other.last
+            && self.last.as_ref().is_none_or(|s| o.ts >= s.ts)
+        {
+            self.last = Some(o.clone());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggSample {
+    pub ts: u64,
+    pub value: f64,
+    pub q: Quality,
+}
+
+impl AggSample {
+    fn from_tuple<T: StorableNum>((ts, v, q): (u64, T, Quality)) -> Option<Self> {
+        Some(Self {
+            ts,
+            value: num_traits::cast(v)?,
+            q,
+        })
+    }
+}
+
+// value of a series at a point in time, with the samples it was derived from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValueAt {
+    pub ts: u64,
+    pub value: Option<f64>,
+    pub q: Quality,
+    // last valid sample at or before ts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<AggSample>,
+    // first valid sample after ts, only used for linear interpolation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<AggSample>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M").unwrap()
+    }
+
+    #[test]
+    fn local_bounds_skip_dst_gap() {
+        let tz = chrono_tz::Europe::Berlin;
+        // 2026-03-29 01:00 - 05:00 local, 02:00 - 03:00 doesn't exist
+        let from = local_to_utc_ms(&tz, local("2026-03-29T01:00")) as u64;
+        let to = local_to_utc_ms(&tz, local("2026-03-29T05:00")) as u64;
+        let (bounds, labels) = local_bucket_bounds(from, to, 15 * 60 * 1000, &tz);
+
+        assert!(bounds.windows(2).all(|w| w[0] < w[1]), "{bounds:?}");
+        assert_eq!(bounds.len(), labels.len() + 1);
+        assert_eq!((bounds[0], *bounds.last().unwrap()), (from, to));
+        // 3 real hours in 15 min buckets
+        assert_eq!(labels.len(), 12);
+        assert!(labels.iter().all(|l| l.format("%H").to_string() != "02"));
+        assert_eq!(labels[4], local("2026-03-29T03:00"));
+
+        // samples land in the bucket of their local time
+        let ts = local_to_utc_ms(&tz, local("2026-03-29T03:20")) as u64;
+        assert_eq!(labels[bucket_index(&bounds, ts)], local("2026-03-29T03:15"));
+    }
+
+    #[test]
+    fn local_bounds_hourly_dst_gap() {
+        let tz = chrono_tz::Europe::Berlin;
+        let from = local_to_utc_ms(&tz, local("2026-03-29T00:00")) as u64;
+        let to = local_to_utc_ms(&tz, local("2026-03-29T04:00")) as u64;
+        let (bounds, labels) = local_bucket_bounds(from, to, 60 * 60 * 1000, &tz);
+
+        assert!(bounds.windows(2).all(|w| w[0] < w[1]), "{bounds:?}");
+        assert_eq!(
+            labels,
+            ["00:00", "01:00", "03:00"].map(|h| local(&format!("2026-03-29T{h}")))
+        );
+    }
+}
@@ -0,0 +1,194 @@
+// Time-weighted average and time integral over the fixed sample grid.
+//
+// The signal is defined from the first to the last valid sample of a range. Between two valid
+// samples it is either held (step) or interpolated linearly, so gaps of missing or filtered
+// samples are bridged. the last sample holds for its own slot.
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::QualityFilter;
+use crate::meta::{BlockMeta, Quality, SizedBlock, StorableNum};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    #[default]
+    Step,
+    Linear,
+}
+
+// Integral of a block between its first and last valid (good | uncertain) sample, in
+// value * sample slots. together with fst_valid/lst_valid of the block meta this is enough to
+// integrate over whole blocks without decoding them. stored next to the block meta.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BlockIntegral {
+    pub step: f64,
+    pub linear: f64,
+}
+
+impl BlockIntegral {
+    pub fn from_samples<T: StorableNum>(vals: &[T], qs: &[Quality]) -> Self {
+        let mut res = Self::default();
+        let mut last: Option<(usize, f64)> = None;
+
+        for i in 0..qs.len() {
+            if !QualityFilter::Valid.accepts(qs[i]) {
+                continue;
+            }
+            let Some(v) = num_traits::cast::<T, f64>(vals[i]) else {
+                continue;
+            };
+            if let Some((j, lv)) = last {
+                let gap = (i - j) as f64;
+                res.step += lv * gap;
+                res.linear += (lv + v) / 2.0 * gap;
+            }
+            last = Some((i, v));
+        }
+
+        res
+    }
+
+    pub fn from_block(block: &SizedBlock) -> Self {
+        match block {
+            SizedBlock::F32Block(_, v, q) => Self::from_samples(v, q),
+            SizedBlock::F64Block(_, v, q) => Self::from_samples(v, q),
+            SizedBlock::I32Block(_, v, q) => Self::from_samples(v, q),
+            SizedBlock::I64Block(_, v, q) => Self::from_samples(v, q),
+            SizedBlock::U32Block(_, v, q) => Self::from_samples(v, q),
+            SizedBlock::U64Block(_, v, q) => Self::from_samples(v, q),
+            SizedBlock::U8Block(_, v, q) => Self::from_samples(v, q),
+        }
+    }
+
+    pub fn get(&self, mode: Interpolation) -> f64 {
+        match mode {
+            Interpolation::Step => self.step,
+            Interpolation::Linear => self.linear,
+        }
+    }
+}
+
+// Running time integral. samples and blocks must be added in ts order.
+#[derive(Debug, Clone)]
+pub struct TimeIntegral {
+    pub mode: Interpolation,
+    pub filter: QualityFilter,
+    sample_ms: u64,
+    // value * ms
+    area: f64,
+    // ms between the first and the last sample
+    covered: u64,
+    last: Option<(u64, f64)>,
+}
+
+impl TimeIntegral {
+    pub fn new(mode: Interpolation, filter: QualityFilter, sample_ms: u64) -> Self {
+        Self {
+            mode,
+            filter,
+            sample_ms,
+            area: 0.0,
+            covered: 0,
+            last: None,
+        }
+    }
+
+    pub fn add_sample<T: StorableNum>(&mut self, ts: u64, v: T, q: Quality) {
+        if !self.filter.accepts(q) {
+            return;
+        }
+        if let Some(v) = num_traits::cast(v) {
+            self.push(ts, v);
+        }
+    }
+
+    fn push(&mut self, ts: u64, v: f64) {
+        if let Some((lts, lv)) = self.last {
+            let gap = ts - lts;
+            self.area += match self.mode {
+                Interpolation::Step => lv * gap as f64,
+                Interpolation::Linear => (lv + v) / 2.0 * gap as f64,
+            };
+            self.covered += gap;
+        }
+        self.last = Some((ts, v));
+    }
+
+    // true if the block can be added via `add_block`
+    pub fn can_use_meta<T: StorableNum>(&self, meta: &BlockMeta<T>) -> bool {
+        self.filter.matches_meta(meta)
+    }
+
+    // adds a whole block via its meta and stored partial integral
+    pub fn add_block<T: StorableNum>(
+        &mut self,
+        block_start: u64,
+        meta: &BlockMeta<T>,
+        partial: &BlockIntegral,
+    ) {
+        debug_assert!(self.can_use_meta(meta));
+        if meta.count_valid == 0 {
+            return;
+        }
+
+        let (Some(fst), Some(lst)) = (
+            num_traits::cast::<T, f64>(meta.fst_valid),
+            num_traits::cast::<T, f64>(meta.lst_valid),
+        ) else {
+            return;
+        };
+
+        // bridge from the previous sample to the first one of the block
+        self.push(
+            block_start + meta.fst_valid_offset as u64 * self.sample_ms,
+            fst,
+        );
+
+        let span = (meta.lst_valid_offset - meta.fst_valid_offset) as u64 * self.sample_ms;
+        self.area += partial.get(self.mode) * self.sample_ms as f64;
+        self.covered += span;
+        self.last = Some((
+            block_start + meta.lst_valid_offset as u64 * self.sample_ms,
+            lst,
+        ));
+    }
+
+    // the integral in value * `unit_ms` and the time weighted average over [.., to)
+    pub fn result(&self, to: u64, unit_ms: u64) -> IntegralResult {
+        let (mut area, mut covered) = (self.area, self.covered);
+        // the last sample holds for its own slot
+        if let Some((ts, v)) = self.last {
+            let slot = self.sample_ms.min(to.saturating_sub(ts));
+            area += v * slot as f64;
+            covered += slot;
+        }
+
+        IntegralResult {
+            integral: (covered > 0).then(|| area / unit_ms as f64),
+            avg: (covered > 0).then(|| area / covered as f64),
+            covered_ms: covered,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegralResult {
+    // time integral in value * unit
+    pub integral: Option<f64>,
+    // time weighted average
+    pub avg: Option<f64>,
+    // time span the signal was defined for
+    pub covered_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegralRow {
+    pub start: u64,
+    pub end: u64,
+    // local wall clock start of the bucket, for buckets aligned to the series timezone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>,
+    #[serde(flatten)]
+    pub result: IntegralResult,
+}
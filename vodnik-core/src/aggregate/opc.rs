@@ -0,0 +1,393 @@
+// OPC UA Part 13 style aggregates.
+//
+// Samples live on a fixed grid, so a non missing sample at `ts` covers the slot
+// [ts, ts + sample_ms). time not covered by any sample is treated as "no data", which
+// counts as bad, same as a bad sample. missing samples are never used as bounds.
+//
+// Deviations from the spec, mostly because we don't have raw values at arbitrary times:
+//  - interval status is derived from the slot coverage with PercentDataGood/PercentDataBad,
+//  - extrapolation after the last value is always stepped (UseSlopedExtrapolation = false),
+//  - durations are in ms, Total is value * seconds.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::meta::{Quality, StorableNum};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpcAggregate {
+    Interpolative,
+    TimeAverage,
+    TimeAverage2,
+    Total,
+    Count,
+    Range,
+    Delta,
+    DurationGood,
+    DurationBad,
+    PercentGood,
+    PercentBad,
+    WorstQuality,
+    StartBound,
+    EndBound,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OpcConfig {
+    // step between values instead of interpolating linearly, e.g. for enumerations
+    pub stepped: bool,
+    pub treat_uncertain_as_bad: bool,
+    // min percentage of good / bad time for an interval to be good / bad
+    pub percent_data_good: u8,
+    pub percent_data_bad: u8,
+}
+
+impl Default for OpcConfig {
+    fn default() -> Self {
+        Self {
+            stepped: false,
+            treat_uncertain_as_bad: false,
+            percent_data_good: 100,
+            percent_data_bad: 100,
+        }
+    }
+}
+
+impl OpcConfig {
+    fn is_bad(&self, q: Quality) -> bool {
+        q.is_bad() || (self.treat_uncertain_as_bad && q.is_uncertain())
+    }
+
+    // values used for calculations, i.e. good and (depending on config) uncertain ones
+    fn is_usable(&self, q: Quality) -> bool {
+        !q.is_missing() && !self.is_bad(q)
+    }
+}
+
+// a single non missing sample
+#[derive(Debug, Clone, Copy)]
+pub struct RawPoint {
+    pub ts: u64,
+    pub value: f64,
+    pub q: Quality,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct OpcValue {
+    pub value: Option<f64>,
+    pub q: Quality,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpcRow {
+    pub start: u64,
+    pub end: u64,
+    // local wall clock start of the interval, for intervals aligned to the series timezone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>,
+    #[serde(flatten)]
+    pub value: OpcValue,
+}
+
+// appends the non missing samples of a block slice inside `range` to `out`
+pub fn push_points<T: StorableNum>(
+    out: &mut Vec<RawPoint>,
+    slice_start: u64,
+    sample_ms: u64,
+    vals: &[T],
+    qs: &[Quality],
+    range: Range<u64>,
+) {
+    for i in 0..qs.len() {
+        let ts = slice_start + i as u64 * sample_ms;
+        if ts < range.start || qs[i].is_missing() {
+            continue;
+        }
+        if ts >= range.end {
+            break;
+        }
+        if let Some(value) = num_traits::cast(vals[i]) {
+            out.push(RawPoint {
+                ts,
+                value,
+                q: qs[i],
+            });
+        }
+    }
+}
+
+// computes `agg` for [start, end). `points` must be sorted by ts and should reach past both
+// ends of the interval, the values around the interval are used as bounds.
+pub fn compute(
+    agg: OpcAggregate,
+    cfg: &OpcConfig,
+    points: &[RawPoint],
+    start: u64,
+    end: u64,
+    sample_ms: u64,
+) -> OpcValue {
+    let iv = Interval {
+        cfg,
+        points,
+        lo: points.partition_point(|p| p.ts < start),
+        hi: points.partition_point(|p| p.ts < end),
+        start,
+        end,
+        sample_ms,
+    };
+
+    match agg {
+        OpcAggregate::Interpolative => iv.value_at(start, true),
+        OpcAggregate::StartBound => iv.value_at(start, false),
+        OpcAggregate::EndBound => iv.value_at(end, false),
+        OpcAggregate::TimeAverage => {
+            let (area, covered) = iv.integral_interpolated();
+            iv.with_status((covered > 0).then(|| area / covered as f64))
+        }
+        OpcAggregate::TimeAverage2 => {
+            let (area, covered) = iv.integral_simple();
+            iv.with_status((covered > 0).then(|| area / covered as f64))
+        }
+        OpcAggregate::Total => {
+            let (area, covered) = iv.integral_interpolated();
+            let secs = (end - start) as f64 / 1000.0;
+            iv.with_status((covered > 0).then(|| area / covered as f64 * secs))
+        }
+        OpcAggregate::Count => iv.with_status(Some(iv.usable().count() as f64)),
+        OpcAggregate::Range => {
+            let range = iv
+                .usable()
+                .fold(None, |acc: Option<(f64, f64)>, p| match acc {
+                    Some((min, max)) => Some((min.min(p.value), max.max(p.value))),
+                    None => Some((p.value, p.value)),
+                });
+            iv.with_status(range.map(|(min, max)| max - min))
+        }
+        OpcAggregate::Delta => {
+            let first = iv.usable().next();
+            let last = iv.usable().last();
+            iv.with_status(first.zip(last).map(|(f, l)| l.value - f.value))
+        }
+        OpcAggregate::DurationGood => good(Some(iv.coverage().good as f64)),
+        OpcAggregate::DurationBad => good(Some(iv.coverage().bad as f64)),
+        OpcAggregate::PercentGood => good(Some(iv.coverage().good as f64 * 100.0 / iv.len())),
+        OpcAggregate::PercentBad => good(Some(iv.coverage().bad as f64 * 100.0 / iv.len())),
+        OpcAggregate::WorstQuality => match iv.worst_quality() {
+            Some(q) => good(Some(q.0 as f64)),
+            None => OpcValue {
+                value: None,
+                q: Quality::MISSING,
+            },
+        },
+    }
+}
+
+fn good(value: Option<f64>) -> OpcValue {
+    OpcValue {
+        value,
+        q: Quality::GOOD,
+    }
+}
+
+fn lerp(a: &RawPoint, b: &RawPoint, ts: u64) -> f64 {
+    if b.ts == a.ts {
+        return a.value;
+    }
+    a.value + (b.value - a.value) * (ts as f64 - a.ts as f64) / (b.ts - a.ts) as f64
+}
+
+#[derive(Debug, Default)]
+struct Coverage {
+    good: u64,
+    uncertain: u64,
+    // bad samples and no data
+    bad: u64,
+}
+
+struct Interval<'a> {
+    cfg: &'a OpcConfig,
+    points: &'a [RawPoint],
+    // points[lo..hi] are inside the interval
+    lo: usize,
+    hi: usize,
+    start: u64,
+    end: u64,
+    sample_ms: u64,
+}
+
+impl Interval<'_> {
+    fn len(&self) -> f64 {
+        (self.end - self.start) as f64
+    }
+
+    fn usable(&self) -> impl DoubleEndedIterator<Item = &RawPoint> {
+        self.points[self.lo..self.hi]
+            .iter()
+            .filter(|p| self.cfg.is_usable(p.q))
+    }
+
+    // the point before the interval start is included, its slot may reach into the interval
+    fn with_slots(&self) -> &[RawPoint] {
+        &self.points[self.lo.saturating_sub(1)..self.hi]
+    }
+
+    fn slot(&self, p: &RawPoint) -> Range<u64> {
+        p.ts.max(self.start)..(p.ts + self.sample_ms).min(self.end)
+    }
+
+    fn coverage(&self) -> Coverage {
+        let mut c = Coverage::default();
+        for p in self.with_slots() {
+            let slot = self.slot(p);
+            if slot.is_empty() {
+                continue;
+            }
+            let len = slot.end - slot.start;
+            if self.cfg.is_bad(p.q) {
+                c.bad += len;
+            } else if p.q.is_uncertain() {
+                c.uncertain += len;
+            } else {
+                c.good += len;
+            }
+        }
+        // uncovered time is no data
+        c.bad += (self.end - self.start).saturating_sub(c.good + c.uncertain + c.bad);
+        c
+    }
+
+    fn status(&self) -> Quality {
+        let c = self.coverage();
+        let len = self.end - self.start;
+        if c.good * 100 >= self.cfg.percent_data_good as u64 * len {
+            Quality::GOOD
+        } else if c.bad * 100 >= self.cfg.percent_data_bad as u64 * len {
+            Quality::BAD
+        } else {
+            Quality::UNCERTAIN
+        }
+    }
+
+    fn with_status(&self, value: Option<f64>) -> OpcValue {
+        match value {
+            Some(v) => OpcValue {
+                value: Some(v),
+                q: self.status(),
+            },
+            None => OpcValue {
+                value: None,
+                q: Quality::MISSING,
+            },
+        }
+    }
+
+    // value at `ts` from the surrounding points. with `usable_only` bad values are skipped,
+    // otherwise they are used and make the result bad.
+    fn value_at(&self, ts: u64, usable_only: bool) -> OpcValue {
+        let accept = |p: &&RawPoint| !usable_only || self.cfg.is_usable(p.q);
+        let split = self.points.partition_point(|p| p.ts <= ts);
+        let prev = self.points[..split].iter().rev().find(accept);
+        let next = self.points[split..].iter().find(accept);
+
+        let Some(prev) = prev else {
+            return OpcValue {
+                value: None,
+                q: Quality::MISSING,
+            };
+        };
+
+        if prev.ts == ts || self.cfg.stepped {
+            return OpcValue {
+                value: Some(prev.value),
+                q: prev.q,
+            };
+        }
+
+        match next {
+            Some(next) => OpcValue {
+                value: Some(lerp(prev, next, ts)),
+                q: prev.q.worse(next.q),
+            },
+            // stepped extrapolation
+            None => OpcValue {
+                value: Some(prev.value),
+                q: prev.q.worse(Quality::UNCERTAIN),
+            },
+        }
+    }
+
+    // integral over the interval, interpolating across bad values and gaps.
+    // returns (value * ms, covered ms). time before the first known value is not covered.
+    fn integral_interpolated(&self) -> (f64, u64) {
+        let usable = |p: &&RawPoint| self.cfg.is_usable(p.q);
+        let prev = self.points[..self.lo].iter().rev().find(usable);
+        let next = self.points[self.hi..].iter().find(usable);
+        let curve: Vec<&RawPoint> = prev.into_iter().chain(self.usable()).chain(next).collect();
+
+        let (mut area, mut covered) = (0.0, 0);
+        for w in curve.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let (x0, x1) = (a.ts.max(self.start), b.ts.min(self.end));
+            if x0 >= x1 {
+                continue;
+            }
+            area += if self.cfg.stepped {
+                a.value * (x1 - x0) as f64
+            } else {
+                (lerp(a, b, x0) + lerp(a, b, x1)) / 2.0 * (x1 - x0) as f64
+            };
+            covered += x1 - x0;
+        }
+
+        // no value after the end, hold the last one
+        if next.is_none()
+            && let Some(last) = curve.last()
+            && last.ts < self.end
+        {
+            let x0 = last.ts.max(self.start);
+            area += last.value * (self.end - x0) as f64;
+            covered += self.end - x0;
+        }
+
+        (area, covered)
+    }
+
+    // integral over the slots of usable values only, bad values and gaps are left out.
+    // returns (value * ms, covered ms)
+    fn integral_simple(&self) -> (f64, u64) {
+        let pts = self.with_slots();
+        let (mut area, mut covered) = (0.0, 0);
+
+        for (i, p) in pts.iter().enumerate() {
+            let slot = self.slot(p);
+            if slot.is_empty() || !self.cfg.is_usable(p.q) {
+                continue;
+            }
+
+            let next = pts
+                .get(i + 1)
+                .or_else(|| self.points.get(self.hi))
+                .filter(|n| n.ts == p.ts + self.sample_ms && self.cfg.is_usable(n.q));
+
+            let len = (slot.end - slot.start) as f64;
+            area += match next {
+                Some(n) if !self.cfg.stepped => {
+                    (lerp(p, n, slot.start) + lerp(p, n, slot.end)) / 2.0 * len
+                }
+                _ => p.value * len,
+            };
+            covered += slot.end - slot.start;
+        }
+
+        (area, covered)
+    }
+
+    fn worst_quality(&self) -> Option<Quality> {
+        self.points[self.lo..self.hi]
+            .iter()
+            .map(|p| p.q)
+            .reduce(Quality::worse)
+    }
+}
@@ -1,12 +1,27 @@
+use chrono::{DateTime, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::warn;
 
-use crate::meta::{Quality, SeriesId, StorageType};
+use crate::helpers;
+use crate::meta::{
+    BlockNumber, ByteStorable, NonFinitePolicy, Quality, SeriesId, SeriesMeta, StorableNum,
+    StorageType,
+};
+
+// content type of the binary `BatchIngest` encoding, see `BatchIngest::to_binary`
+pub const BINARY_BATCH_CONTENT_TYPE: &str = "application/x-vodnik-batch";
+// `BatchIngest::batch_id` of requests that can't carry it in the body, e.g. binary batches
+pub const BATCH_ID_HEADER: &str = "x-vodnik-batch-id";
+// id of the operation a write request was recorded under, see POST /operations/{op}/revert
+pub const OPERATION_HEADER: &str = "x-vodnik-operation";
+const BINARY_BATCH_VERSION: u8 = 1;
+
+const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
 
 #[derive(Debug, Error)]
 pub enum IngestError {
-    #[error("timestamp and value length mismatch")]
+    #[error("timestamp, value and quality length mismatch")]
     LengthMismatch,
 
     #[error("invalid timestamp: {0}")]
@@ -14,22 +29,201 @@ pub enum IngestError {
 
     #[error("value type does not match series type")]
     TypeMismatch,
+
+    #[error("invalid binary batch: {0}")]
+    InvalidBinary(String),
+
+    #[error("{} samples rejected", .0.len())]
+    Rejected(Vec<SampleRejection>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    // NaN or +-inf, and the series rejects them
+    NonFinite,
+    // quality byte with the major bits unused by OPC (10_SSSS_LL)
+    InvalidQuality,
+    // later than now + the max future offset of the series
+    FutureTimestamp,
+    // earlier than the timestamp before it
+    Unsorted,
+    // not at the start of a sample slot, overwrites have to hit the slots exactly
+    OffGrid,
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::NonFinite => write!(f, "non finite value"),
+            RejectReason::InvalidQuality => write!(f, "invalid quality"),
+            RejectReason::FutureTimestamp => write!(f, "timestamp too far in the future"),
+            RejectReason::Unsorted => write!(f, "timestamps not sorted in ascending order"),
+            RejectReason::OffGrid => write!(f, "timestamp not on the sample grid"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SampleRejection {
+    // position of the sample in the batch
+    pub index: usize,
+    pub reason: RejectReason,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(try_from = "RawBatchIngest")]
 pub struct BatchIngest {
     pub series: SeriesId,
-    // assume UNIX TS in ms (aka ms after UNIX EPOCH) for now
-    // once we have ICU support, we'll also support parsing ts.
+    // UNIX TS in ms (aka ms after UNIX EPOCH). requests may send other units or strings, see
+    // `RawTimestamp`, they are normalised while deserializing.
     pub ts: Vec<u64>,
     pub qs: Vec<Quality>,
     #[serde(flatten)]
     pub vals: ValueVec,
+    // client chosen id, a retried batch with the same id is only written once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_id: Option<String>,
+}
+
+// `BatchIngest` as sent by clients, before the timestamps are normalised to ms
+#[derive(Deserialize)]
+struct RawBatchIngest {
+    series: SeriesId,
+    ts: Vec<RawTimestamp>,
+    qs: Vec<Quality>,
+    #[serde(default)]
+    batch_id: Option<String>,
+    #[serde(flatten)]
+    format: TimestampFormat,
+    #[serde(flatten)]
+    vals: ValueVec,
+}
+
+impl TryFrom<RawBatchIngest> for BatchIngest {
+    type Error = IngestError;
+
+    fn try_from(raw: RawBatchIngest) -> Result<Self, Self::Error> {
+        let ts = raw
+            .ts
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                raw.format.to_ms(t).map_err(|e| match e {
+                    IngestError::InvalidTimestamp(msg) => {
+                        IngestError::InvalidTimestamp(format!("ts[{i}]: {msg}"))
+                    }
+                    e => e,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(BatchIngest {
+            series: raw.series,
+            ts,
+            qs: raw.qs,
+            vals: raw.vals,
+            batch_id: raw.batch_id,
+        })
+    }
+}
+
+// a timestamp as sent by clients, either a number after UNIX EPOCH in `TimestampFormat::unit` or
+// a string. strings are RFC 3339 / ISO 8601 with an offset, e.g. 2026-03-29T02:30:00+02:00, or
+// naive local times like 2026-03-29 02:30:00.5 interpreted in `TimestampFormat::zone`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum RawTimestamp {
+    Epoch(u64),
+    Text(String),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EpochUnit {
+    S,
+    #[default]
+    Ms,
+    Us,
+    Ns,
+}
+
+// how naive local times that don't map to exactly one instant are resolved
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DstPolicy {
+    // ambiguous times (DST fall back) take the first occurrence, non existent times (DST spring
+    // forward) are shifted forward by the gap length
+    #[default]
+    Earlier,
+    // ambiguous times take the second occurrence, non existent times are shifted forward
+    Later,
+    // both are rejected
+    Reject,
+}
+
+// how the timestamps of a request are interpreted, sent next to them as
+// "ts_unit", "ts_zone" and "ts_dst" (or as query parameters of /batch/stream)
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct TimestampFormat {
+    #[serde(default, rename = "ts_unit")]
+    pub unit: EpochUnit,
+    // IANA timezone of naive local time strings, they are rejected without one
+    #[serde(default, rename = "ts_zone", skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    #[serde(default, rename = "ts_dst")]
+    pub dst: DstPolicy,
+}
+
+impl TimestampFormat {
+    // normalises a timestamp to ms after UNIX EPOCH, sub ms precision is truncated
+    pub fn to_ms(&self, ts: &RawTimestamp) -> Result<u64, IngestError> {
+        match ts {
+            RawTimestamp::Epoch(v) => self.epoch_to_ms(*v),
+            RawTimestamp::Text(s) => self.parse(s.trim()),
+        }
+    }
+
+    fn epoch_to_ms(&self, v: u64) -> Result<u64, IngestError> {
+        match self.unit {
+            EpochUnit::S => v
+                .checked_mul(1000)
+                .ok_or_else(|| IngestError::InvalidTimestamp(format!("{v}s is out of range"))),
+            EpochUnit::Ms => Ok(v),
+            EpochUnit::Us => Ok(v / 1000),
+            EpochUnit::Ns => Ok(v / 1_000_000),
+        }
+    }
+
+    fn parse(&self, s: &str) -> Result<u64, IngestError> {
+        let invalid = |msg: &str| IngestError::InvalidTimestamp(format!("'{s}' {msg}"));
+
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            return self.epoch_to_ms(s.parse().map_err(|_| invalid("is out of range"))?);
+        }
+
+        let utc_ms = if let Ok(dt) = DateTime::parse_from_rfc3339(s)
+            .or_else(|_| DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f%z"))
+        {
+            dt.timestamp_millis()
+        } else {
+            let local = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+                .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+                .map_err(|_| invalid("is neither RFC 3339 nor a local date time"))?;
+            let Some(zone) = &self.zone else {
+                return Err(invalid("has no UTC offset and no ts_zone is given"));
+            };
+            let tz = helpers::parse_timezone(zone).map_err(IngestError::InvalidTimestamp)?;
+            helpers::resolve_local(&tz, local, self.dst)
+                .ok_or_else(|| invalid(&format!("is ambiguous or doesn't exist in {zone}")))?
+        };
+
+        u64::try_from(utc_ms).map_err(|_| invalid("is before UNIX EPOCH"))
+    }
 }
 
 impl BatchIngest {
     pub fn validate(&self) -> Result<(), IngestError> {
-        if self.ts.len() != self.vals.len() && self.vals.len() == self.qs.len() {
+        if self.ts.len() != self.vals.len() || self.vals.len() != self.qs.len() {
             warn!("length mismatch");
             return Err(IngestError::LengthMismatch);
         }
@@ -40,33 +234,366 @@ impl BatchIngest {
             ));
         }
 
-        for window in self.ts.windows(2) {
-            let t1 = window[0];
-            let t2 = window[1];
-            if t1 > t2 {
-                return Err(IngestError::InvalidTimestamp(
-                    "timestamps must be sorted in ascending order".to_string(),
-                ));
-            }
+        let unsorted: Vec<_> = self
+            .ts
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] > w[1])
+            .map(|(i, _)| SampleRejection {
+                index: i + 1,
+                reason: RejectReason::Unsorted,
+            })
+            .collect();
+        if !unsorted.is_empty() {
+            return Err(IngestError::Rejected(unsorted));
         }
 
-        // TODO: for float values we need to reject NaN
-
+        // values and qualities are checked against the series policy, see `apply_ingest_policy`
         Ok(())
     }
 
     pub fn check_type(&self, stype: StorageType) -> Result<(), IngestError> {
-        match (&self.vals, stype) {
-            (ValueVec::F32(_), StorageType::Float32) => Ok(()),
-            (ValueVec::F64(_), StorageType::Float64) => Ok(()),
-            (ValueVec::I32(_), StorageType::Int32) => Ok(()),
-            (ValueVec::I64(_), StorageType::Int64) => Ok(()),
-            (ValueVec::U32(_), StorageType::UInt32) => Ok(()),
-            (ValueVec::U64(_), StorageType::UInt64) => Ok(()),
-            (ValueVec::Enum(_), StorageType::Enumeration) => Ok(()),
-            _ => Err(IngestError::TypeMismatch),
+        self.vals.check_type(stype)
+    }
+}
+
+// checks the timestamp and quality of a single sample against the ingest policy of the series
+pub fn check_sample(
+    series: &SeriesMeta,
+    now_ms: u64,
+    ts: u64,
+    q: Quality,
+) -> Result<(), RejectReason> {
+    if Quality::try_from(q.0).is_err() {
+        return Err(RejectReason::InvalidQuality);
+    }
+    if series.max_future_ms > 0 && ts > now_ms.saturating_add(series.max_future_ms) {
+        return Err(RejectReason::FutureTimestamp);
+    }
+    Ok(())
+}
+
+// checks all samples against the ingest policy of the series. non finite values are replaced
+// by a bad sample if the series stores them, every other violation is returned as a rejection.
+pub fn apply_ingest_policy<T: StorableNum>(
+    series: &SeriesMeta,
+    now_ms: u64,
+    ts: &[u64],
+    vals: &mut [T],
+    qs: &mut [Quality],
+) -> Result<(), IngestError> {
+    let mut rejections = vec![];
+
+    for i in 0..ts.len() {
+        let non_finite = vals[i].to_f64().is_some_and(|v| !v.is_finite());
+        let res = match check_sample(series, now_ms, ts[i], qs[i]) {
+            Ok(()) if non_finite => match series.non_finite {
+                NonFinitePolicy::Reject => Err(RejectReason::NonFinite),
+                NonFinitePolicy::StoreBad => {
+                    vals[i] = T::zero();
+                    qs[i] = Quality::BAD;
+                    Ok(())
+                }
+            },
+            res => res,
+        };
+
+        if let Err(reason) = res {
+            rejections.push(SampleRejection { index: i, reason });
         }
     }
+
+    if rejections.is_empty() {
+        Ok(())
+    } else {
+        Err(IngestError::Rejected(rejections))
+    }
+}
+
+// many series in one request, each batch is validated and written on its own
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MultiBatchIngest {
+    pub batches: Vec<BatchIngest>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MultiBatchResult {
+    pub ok: usize,
+    pub failed: usize,
+    // one entry per batch, in request order
+    pub results: Vec<BatchResult>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchResult {
+    pub series: SeriesId,
+    // http status the batch would have gotten from /batch
+    pub status: u16,
+    // samples written, including merged ones
+    pub samples: usize,
+    // samples resolved by the collision policy of the series
+    pub merged: usize,
+    // samples dropped by the alignment or collision policy of the series
+    pub rejected: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // samples failing validation, the batch isn't written if there are any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rejections: Vec<SampleRejection>,
+}
+
+// PUT /series/{id}/data, replaces the values of a range. the samples are stored with
+// `Quality::OVERWRITTEN`, the replaced block versions are kept and listed in the audit trail.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OverwriteRequest {
+    // ms after UNIX EPOCH, each on the start of a sample slot
+    pub ts: Vec<u64>,
+    #[serde(flatten)]
+    pub vals: ValueVec,
+    // who corrected the data and why
+    pub user: String,
+    pub reason: String,
+}
+
+impl OverwriteRequest {
+    pub fn validate(&self) -> Result<(), IngestError> {
+        if self.ts.len() != self.vals.len() {
+            return Err(IngestError::LengthMismatch);
+        }
+        if self.ts.is_empty() {
+            return Err(IngestError::InvalidTimestamp(
+                "no timestamps given".to_string(),
+            ));
+        }
+
+        let unsorted: Vec<_> = self
+            .ts
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] > w[1])
+            .map(|(i, _)| SampleRejection {
+                index: i + 1,
+                reason: RejectReason::Unsorted,
+            })
+            .collect();
+        if !unsorted.is_empty() {
+            return Err(IngestError::Rejected(unsorted));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct OverwriteResult {
+    pub samples: u64,
+    // blocks rewritten, one audit record each
+    pub blocks: u64,
+}
+
+// a block touched by a revert. the block is pointed back to `previous_object_key`, a block
+// created by the operation (no previous version) is replaced by an empty one.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RevertBlock {
+    pub series: SeriesId,
+    pub block: BlockNumber,
+    // latest version written by the operation
+    pub object_key: String,
+    pub previous_object_key: Option<String>,
+    // versions written after the operation, they are reverted too
+    pub later_versions: u64,
+    // other requests whose samples in the block are reverted too, a block flushed from memory
+    // holds the samples of all requests since it was created
+    pub other_operations: Vec<String>,
+    // the block is held in memory, it can't be reverted before it is flushed
+    pub hot: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RevertResult {
+    pub dry_run: bool,
+    pub blocks: Vec<RevertBlock>,
+}
+
+// result of a garbage collection run, see POST /gc
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    // block versions dropped by the retention policy
+    pub versions_pruned: u64,
+    // objects found under data/
+    pub objects: u64,
+    // unreferenced objects past the grace period, deleted unless dry_run
+    pub deleted: u64,
+    pub bytes_reclaimed: u64,
+}
+
+// result of DELETE /series/{id}/data
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DeleteResult {
+    // samples marked as manually deleted
+    pub deleted: u64,
+    // blocks rewritten
+    pub blocks: u64,
+}
+
+// Binary layout, all little endian, columnar like `WalEntry::Write`:
+// [VERSION u8][TYPE u8][SERIES u64][COUNT u32][TS u64 * COUNT][VALUES T * COUNT][QS u8 * COUNT][CRC u32]
+// TYPE is the index of the `ValueVec` variant (f32 = 0 .. enum = 6), CRC is CRC-32C over all
+// preceding bytes. the batch id isn't encoded, it is sent in the BATCH_ID_HEADER.
+impl BatchIngest {
+    pub fn to_binary(&self) -> Vec<u8> {
+        let count = self.ts.len();
+        let mut buf =
+            Vec::with_capacity(1 + 1 + 8 + 4 + count * (8 + 1) + count * self.vals.type_size() + 4);
+
+        buf.push(BINARY_BATCH_VERSION);
+        buf.push(self.vals.type_code());
+        buf.extend_from_slice(&self.series.0.get().to_le_bytes());
+        buf.extend_from_slice(&(count as u32).to_le_bytes());
+        put_le(&mut buf, &self.ts);
+        match &self.vals {
+            ValueVec::F32(v) => put_le(&mut buf, v),
+            ValueVec::F64(v) => put_le(&mut buf, v),
+            ValueVec::I32(v) => put_le(&mut buf, v),
+            ValueVec::I64(v) => put_le(&mut buf, v),
+            ValueVec::U32(v) => put_le(&mut buf, v),
+            ValueVec::U64(v) => put_le(&mut buf, v),
+            ValueVec::Enum(v) => put_le(&mut buf, v),
+        }
+        buf.extend(self.qs.iter().map(|q| q.0));
+
+        let crc = CRC.checksum(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, IngestError> {
+        let err = |msg: &str| IngestError::InvalidBinary(msg.to_string());
+
+        let Some((payload, crc)) = bytes.split_last_chunk::<4>() else {
+            return Err(err("too short"));
+        };
+        if CRC.checksum(payload) != u32::from_le_bytes(*crc) {
+            return Err(err("crc mismatch"));
+        }
+
+        let mut r = LeReader { buf: payload };
+        let version = r.take(1).ok_or_else(|| err("missing header"))?[0];
+        if version != BINARY_BATCH_VERSION {
+            return Err(IngestError::InvalidBinary(format!(
+                "unsupported version {version}"
+            )));
+        }
+        let code = r.take(1).ok_or_else(|| err("missing header"))?[0];
+        let series = r
+            .vec::<u64>(1)
+            .and_then(|v| std::num::NonZero::new(v[0]))
+            .ok_or_else(|| err("invalid series id"))?;
+        let count = r.vec::<u32>(1).ok_or_else(|| err("missing count"))?[0] as usize;
+
+        let truncated = || err("truncated");
+        let ts = r.vec::<u64>(count).ok_or_else(truncated)?;
+        let vals = match code {
+            0 => ValueVec::F32(r.vec(count).ok_or_else(truncated)?),
+            1 => ValueVec::F64(r.vec(count).ok_or_else(truncated)?),
+            2 => ValueVec::I32(r.vec(count).ok_or_else(truncated)?),
+            3 => ValueVec::I64(r.vec(count).ok_or_else(truncated)?),
+            4 => ValueVec::U32(r.vec(count).ok_or_else(truncated)?),
+            5 => ValueVec::U64(r.vec(count).ok_or_else(truncated)?),
+            6 => ValueVec::Enum(r.vec(count).ok_or_else(truncated)?),
+            _ => {
+                return Err(IngestError::InvalidBinary(format!(
+                    "unknown value type {code}"
+                )));
+            }
+        };
+        let qs = r
+            .vec::<u8>(count)
+            .ok_or_else(truncated)?
+            .into_iter()
+            .map(Quality)
+            .collect();
+
+        if !r.buf.is_empty() {
+            return Err(err("trailing bytes"));
+        }
+
+        Ok(BatchIngest {
+            series: SeriesId(series),
+            ts,
+            qs,
+            vals,
+            batch_id: None,
+        })
+    }
+}
+
+fn put_le<T: ByteStorable>(buf: &mut Vec<u8>, vals: &[T]) {
+    let size = size_of::<T>();
+    for v in vals {
+        let at = buf.len();
+        buf.resize(at + size, 0);
+        v.write_le_bytes(&mut buf[at..]);
+    }
+}
+
+struct LeReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> LeReader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.buf.len() {
+            return None;
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Some(head)
+    }
+
+    fn vec<T: ByteStorable>(&mut self, count: usize) -> Option<Vec<T>> {
+        let size = size_of::<T>();
+        let bytes = self.take(count.checked_mul(size)?)?;
+        Some(bytes.chunks_exact(size).map(T::read_le_bytes).collect())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StreamIngestResult {
+    // non empty lines read
+    pub lines: u64,
+    pub samples: u64,
+    pub merged: u64,
+    pub rejected: u64,
+    pub series: Vec<SeriesIngestCount>,
+}
+
+// error response of POST /batch/stream. the samples of the lines before the invalid one are
+// written anyway, under the operation named in the response header
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StreamIngestError {
+    pub error: String,
+    #[serde(flatten)]
+    pub written: StreamIngestResult,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SeriesIngestCount {
+    pub series: SeriesId,
+    pub accepted: u64,
+    pub merged: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SeriesData {
+    pub series: SeriesId,
+    // UNIX TS in ms of the start of each sample slot
+    pub ts: Vec<u64>,
+    pub qs: Vec<Quality>,
+    #[serde(flatten)]
+    pub vals: ValueVec,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -103,6 +630,44 @@ impl std::fmt::Debug for ValueVec {
 }
 
 impl ValueVec {
+    // an empty vec matching the storage type
+    pub fn with_type(stype: StorageType) -> Self {
+        match stype {
+            StorageType::Float32 => ValueVec::F32(vec![]),
+            StorageType::Float64 => ValueVec::F64(vec![]),
+            StorageType::Int32 => ValueVec::I32(vec![]),
+            StorageType::Int64 => ValueVec::I64(vec![]),
+            StorageType::UInt32 => ValueVec::U32(vec![]),
+            StorageType::UInt64 => ValueVec::U64(vec![]),
+            StorageType::Enumeration => ValueVec::Enum(vec![]),
+        }
+    }
+
+    // type code of the binary batch encoding
+    fn type_code(&self) -> u8 {
+        match self {
+            ValueVec::F32(_) => 0,
+            ValueVec::F64(_) => 1,
+            ValueVec::I32(_) => 2,
+            ValueVec::I64(_) => 3,
+            ValueVec::U32(_) => 4,
+            ValueVec::U64(_) => 5,
+            ValueVec::Enum(_) => 6,
+        }
+    }
+
+    fn type_size(&self) -> usize {
+        match self {
+            ValueVec::F32(_) => size_of::<f32>(),
+            ValueVec::F64(_) => size_of::<f64>(),
+            ValueVec::I32(_) => size_of::<i32>(),
+            ValueVec::I64(_) => size_of::<i64>(),
+            ValueVec::U32(_) => size_of::<u32>(),
+            ValueVec::U64(_) => size_of::<u64>(),
+            ValueVec::Enum(_) => size_of::<u8>(),
+        }
+    }
+
     pub fn len(&self) -> usize {
         match self {
             ValueVec::F32(v) => v.len(),
@@ -118,4 +683,17 @@ impl ValueVec {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    pub fn check_type(&self, stype: StorageType) -> Result<(), IngestError> {
+        match (self, stype) {
+            (ValueVec::F32(_), StorageType::Float32) => Ok(()),
+            (ValueVec::F64(_), StorageType::Float64) => Ok(()),
+            (ValueVec::I32(_), StorageType::Int32) => Ok(()),
+            (ValueVec::I64(_), StorageType::Int64) => Ok(()),
+            (ValueVec::U32(_), StorageType::UInt32) => Ok(()),
+            (ValueVec::U64(_), StorageType::UInt64) => Ok(()),
+            (ValueVec::Enum(_), StorageType::Enumeration) => Ok(()),
+            _ => Err(IngestError::TypeMismatch),
+        }
+    }
 }
@@ -1,8 +1,14 @@
 use std::num::NonZero;
+use std::ops::Range;
 
+use chrono::{DateTime, LocalResult, NaiveDateTime, Offset, TimeDelta, TimeZone};
+use chrono_tz::Tz;
 use tracing::info;
 
-use crate::meta::{BlockLength, SampleLength, SeriesMeta, StorageType, TimeResolution};
+use crate::api::DstPolicy;
+use crate::meta::{
+    AlignPolicy, BlockLength, SampleLength, SeriesMeta, StorageType, TimeResolution,
+};
 
 pub fn get_block_start_as_offset(meta: &SeriesMeta, block_id: u64) -> u64 {
     let res: u64 = meta.block_resolution.into();
@@ -19,6 +25,29 @@ pub fn get_sample_offset(meta: &SeriesMeta, delta_from_block_start: u64) -> u64
     delta_from_block_start / (meta.sample_length.0.get() * res)
 }
 
+// slots of a block whose timestamps lie in [from, to)
+pub fn slots_in_range(meta: &SeriesMeta, block_id: u64, from: u64, to: u64) -> Range<usize> {
+    let bl_start = get_block_start_as_offset(meta, block_id);
+    let sample = duration(meta.sample_resolution, meta.sample_length.0);
+    let len = get_block_length(meta);
+    let slot = |t: u64| t.saturating_sub(bl_start).div_ceil(sample).min(len) as usize;
+
+    slot(from)..slot(to).max(slot(from))
+}
+
+// maps a timestamp onto the start of its sample slot, None if the alignment policy rejects it
+pub fn align_to_grid(meta: &SeriesMeta, unix_ms: u64) -> Option<u64> {
+    let sample = duration(meta.sample_resolution, meta.sample_length.0);
+    let floor = unix_ms - unix_ms % sample;
+
+    match meta.alignment {
+        AlignPolicy::Floor => Some(floor),
+        AlignPolicy::Nearest if (unix_ms - floor) * 2 >= sample => floor.checked_add(sample),
+        AlignPolicy::Nearest => Some(floor),
+        AlignPolicy::Reject => (floor == unix_ms).then_some(floor),
+    }
+}
+
 pub fn get_block_length(meta: &SeriesMeta) -> u64 {
     let block_duration: u64 = duration(meta.block_resolution, meta.block_length.0);
     let sample_duration: u64 = duration(meta.sample_resolution, meta.sample_length.0);
@@ -30,6 +59,77 @@ pub fn duration(res: TimeResolution, len: NonZero<u64>) -> u64 {
     len.get() * res
 }
 
+// parses durations like 500ms, 15s, 15m, 1h or 1d into ms
+pub fn parse_interval(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in interval '{s}'"))?;
+    let (num, unit) = s.split_at(split);
+
+    let num: u64 = num
+        .parse()
+        .map_err(|_| format!("invalid number in interval '{s}'"))?;
+
+    let unit_ms: u64 = match unit {
+        "ms" => TimeResolution::Millisecond.into(),
+        "s" => TimeResolution::Second.into(),
+        "m" | "min" => TimeResolution::Minute.into(),
+        "h" => TimeResolution::Hour.into(),
+        "d" => 24 * u64::from(TimeResolution::Hour),
+        _ => return Err(format!("unknown unit '{unit}' in interval '{s}'")),
+    };
+
+    match num.checked_mul(unit_ms) {
+        Some(0) | None => Err(format!("invalid interval '{s}'")),
+        Some(ms) => Ok(ms),
+    }
+}
+
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.parse::<Tz>()
+        .map_err(|_| format!("unknown IANA timezone '{name}'"))
+}
+
+pub fn utc_ms_to_local(tz: &Tz, unix_ms: i64) -> Option<NaiveDateTime> {
+    let utc = DateTime::from_timestamp_millis(unix_ms)?;
+    Some(utc.with_timezone(tz).naive_local())
+}
+
+// resolves a local wall clock time to UTC ms.
+// ambiguous times (DST fall back) resolve to the earlier instant. non existent times (DST spring forward)
+// are interpreted with the offset in effect before the gap, i.e. they are shifted forward by the gap length.
+pub fn local_to_utc_ms(tz: &Tz, local: NaiveDateTime) -> i64 {
+    resolve_local(tz, local, DstPolicy::Earlier).expect("only DstPolicy::Reject fails")
+}
+
+// like `local_to_utc_ms`, with the policy picking the instant of ambiguous times. non existent
+// times are shifted forward unless the policy rejects them, rejected times return None.
+pub fn resolve_local(tz: &Tz, local: NaiveDateTime, policy: DstPolicy) -> Option<i64> {
+    match (tz.from_local_datetime(&local), policy) {
+        (LocalResult::Single(dt), _) => Some(dt.timestamp_millis()),
+        (LocalResult::Ambiguous(earliest, _), DstPolicy::Earlier) => {
+            Some(earliest.timestamp_millis())
+        }
+        (LocalResult::Ambiguous(_, latest), DstPolicy::Later) => Some(latest.timestamp_millis()),
+        (LocalResult::None, DstPolicy::Earlier | DstPolicy::Later) => {
+            let before = tz
+                .offset_from_utc_datetime(&(local - TimeDelta::days(1)))
+                .fix();
+            Some(
+                (local - TimeDelta::seconds(before.local_minus_utc() as i64))
+                    .and_utc()
+                    .timestamp_millis(),
+            )
+        }
+        (_, DstPolicy::Reject) => None,
+    }
+}
+
+pub fn format_local(local: NaiveDateTime) -> String {
+    local.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
 pub fn derive_block_size(
     storage_type: StorageType,
     sample_res: TimeResolution,
@@ -1,3 +1,4 @@
+pub mod aggregate;
 pub mod api;
 pub mod codec;
 pub mod helpers;
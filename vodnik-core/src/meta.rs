@@ -1,8 +1,11 @@
+use crate::api::ValueVec;
 use crate::helpers;
 use crate::wal::TxId;
 use num_traits::{Bounded, Num, NumAssign, NumCast};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::ops::Range;
 use std::{fmt, num::NonZero};
 
 pub trait SafeAdd: Copy {
@@ -235,6 +238,18 @@ impl<T: StorableNum> BlockMeta<T> {
     pub const ACC_UNCERTAIN: u32 = 1 << 2;
     pub const ACC_NODATA: u32 = 1 << 3;
 
+    pub fn qual_flag(q: Quality) -> u32 {
+        if q.is_missing() {
+            Self::ACC_NODATA
+        } else if q.is_bad() {
+            Self::ACC_BAD
+        } else if q.is_uncertain() {
+            Self::ACC_UNCERTAIN
+        } else {
+            Self::ACC_GOOD
+        }
+    }
+
     // does a full scan
     pub fn recalc_block_data_full(
         &mut self,
@@ -275,15 +290,7 @@ impl<T: StorableNum> BlockMeta<T> {
             let q = updated_quality_data[i];
             let idx = i as u32;
 
-            let current_qual_flag = if q.is_missing() {
-                Self::ACC_NODATA
-            } else if q.is_bad() {
-                Self::ACC_BAD
-            } else if q.is_uncertain() {
-                Self::ACC_UNCERTAIN
-            } else {
-                Self::ACC_GOOD
-            };
+            let current_qual_flag = Self::qual_flag(q);
 
             self.qual_acc_or |= current_qual_flag;
             self.qual_acc_and &= current_qual_flag;
@@ -365,17 +372,32 @@ impl Quality {
     // SSSS -> SubStatus
     // LL -> Limit
 
-    const GOOD: Self = Self(0b11_0000_00); // 192 (0xC0)
-    const BAD: Self = Self(0b00_0000_00); // 1 (0x00)
-    const UNCERTAIN: Self = Self(0b01_0000_00); // 64 (0x40)
+    pub const GOOD: Self = Self(0b11_0000_00); // 192 (0xC0)
+    pub const BAD: Self = Self(0b00_0000_00); // 1 (0x00)
+    pub const UNCERTAIN: Self = Self(0b01_0000_00); // 64 (0x40)
 
     pub const MISSING: Self = Self(0b10_0000_00); // opc doesnt use 10_SSSS_LL
+    // missing with our own substatus, the stored value is kept
+    pub const DELETED: Self = Self::MISSING.with_sub(0b0010); // 136 (0x88), manually deleted
+
+    // good with OPC substatus local override, used for manually overwritten samples
+    pub const OVERWRITTEN: Self = Self::GOOD.with_sub(0b0110); // 216 (0xD8)
+
+    // bad with OPC substatus
+    pub const BAD_CONFIG_ERROR: Self = Self::BAD.with_sub(0b0001); // 4 (0x04)
+    pub const BAD_DEVICE_FAILURE: Self = Self::BAD.with_sub(0b0011); // 12 (0x0C)
+    pub const BAD_COMM_FAILURE: Self = Self::BAD.with_sub(0b0110); // 24 (0x18)
 
     // masks
     pub const MASK_MAJOR: u8 = 0b11_0000_00;
     pub const MASK_SUB: u8 = 0b00_1110_00;
     pub const MASK_LIMIT: u8 = 0b00_0001_11;
 
+    // sets the SSSS bits
+    const fn with_sub(self, sub: u8) -> Self {
+        Self(self.0 | sub << 2)
+    }
+
     pub fn is_good(self) -> bool {
         (self.0 & Self::MASK_MAJOR) == Self::GOOD.0
     }
@@ -391,6 +413,35 @@ impl Quality {
     pub fn is_missing(self) -> bool {
         (self.0 & Self::MASK_MAJOR) == Self::MISSING.0
     }
+
+    pub fn is_overwritten(self) -> bool {
+        (self.0 & (Self::MASK_MAJOR | Self::MASK_SUB)) == Self::OVERWRITTEN.0
+    }
+
+    pub fn is_deleted(self) -> bool {
+        (self.0 & (Self::MASK_MAJOR | Self::MASK_SUB)) == Self::DELETED.0
+    }
+
+    // good > uncertain > bad > missing, substatus and limit bits are ignored
+    pub fn rank(self) -> u8 {
+        if self.is_good() {
+            3
+        } else if self.is_uncertain() {
+            2
+        } else if self.is_bad() {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn worse(self, other: Self) -> Self {
+        if other.rank() < self.rank() {
+            other
+        } else {
+            self
+        }
+    }
 }
 
 impl Default for Quality {
@@ -405,6 +456,8 @@ impl fmt::Debug for Quality {
             write!(f, "Quality(Good)")
         } else if self.is_bad() {
             write!(f, "Quality(Bad bits={:08b})", self.0)
+        } else if self.is_deleted() {
+            write!(f, "DELETED")
         } else if self.is_missing() {
             write!(f, "MISSING")
         } else {
@@ -416,13 +469,18 @@ impl fmt::Debug for Quality {
 impl TryFrom<u8> for Quality {
     type Error = ();
 
+    // rejects the major bits OPC doesn't use, they are reserved for MISSING
     fn try_from(value: u8) -> Result<Self, Self::Error> {
-        // TODO: Add validation
+        if value & Self::MASK_MAJOR == Self::MISSING.0 {
+            return Err(());
+        }
         Ok(Self(value))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[derive(
+    Debug, Clone, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize,
+)]
 pub enum SizedBlock {
     F32Block(BlockMeta<f32>, Vec<f32>, Vec<Quality>),
     F64Block(BlockMeta<f64>, Vec<f64>, Vec<Quality>),
@@ -435,6 +493,8 @@ pub enum SizedBlock {
 
 pub struct WriteBatch<'a, T: StorableNum> {
     pub tx: TxId,
+    // operation (request) the batch belongs to, stored with the block versions it writes
+    pub op: Option<&'a str>,
     pub series: &'a SeriesMeta,
     pub block_id: BlockNumber,
     pub ts: &'a [u64], // ms after UNIX epoch
@@ -466,34 +526,121 @@ impl<'a, T: StorableNum> WriteBatch<'a, T> {
             vals,
             qs,
             tx,
+            op: None,
+        }
+    }
+
+    pub fn with_op(mut self, op: Option<&'a str>) -> Self {
+        self.op = op;
+        self
+    }
+}
+
+// outcome of writing samples into a block
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteStats {
+    // samples accepted into the block, including merged ones
+    pub samples: u64,
+    // samples that hit an already written slot and were resolved by the collision policy
+    pub merged: u64,
+    // samples dropped by the alignment or collision policy
+    pub rejected: u64,
+}
+
+impl std::ops::AddAssign for WriteStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.samples += rhs.samples;
+        self.merged += rhs.merged;
+        self.rejected += rhs.rejected;
+    }
+}
+
+// writes the samples of a batch into the slots of a block, a slot is taken if its quality
+// isn't missing
+fn write_samples<T: StorableNum>(
+    batch: &WriteBatch<T>,
+    vals: &mut [T],
+    qs: &mut [Quality],
+) -> WriteStats {
+    let bl_start = helpers::get_block_start_as_offset(batch.series, batch.block_id.0);
+    let mut stats = WriteStats::default();
+    // running (sum, count) of averaged slots, a previously stored sample counts once
+    let mut averages: HashMap<usize, (f64, u32)> = HashMap::new();
+
+    for i in 0..batch.ts.len() {
+        let idx = helpers::get_sample_offset(batch.series, batch.ts[i] - bl_start) as usize;
+        let (v, q) = (batch.vals[i], batch.qs[i]);
+
+        if qs[idx].is_missing() {
+            vals[idx] = v;
+            qs[idx] = q;
+            stats.samples += 1;
+            continue;
+        }
+
+        match batch.series.collision {
+            CollisionPolicy::Reject => {
+                stats.rejected += 1;
+                continue;
+            }
+            CollisionPolicy::LastWins => {
+                vals[idx] = v;
+                qs[idx] = q;
+            }
+            CollisionPolicy::FirstWins => {}
+            CollisionPolicy::BetterQuality => {
+                // same quality: last wins
+                if q.rank() >= qs[idx].rank() {
+                    vals[idx] = v;
+                    qs[idx] = q;
+                }
+            }
+            CollisionPolicy::Average => {
+                let (sum, n) = averages
+                    .entry(idx)
+                    .or_insert_with(|| (vals[idx].to_f64().unwrap_or_default(), 1));
+                *sum += v.to_f64().unwrap_or_default();
+                *n += 1;
+                vals[idx] = from_f64_rounded(*sum / *n as f64).unwrap_or(v);
+                qs[idx] = qs[idx].worse(q);
+            }
         }
+        stats.samples += 1;
+        stats.merged += 1;
+    }
+
+    stats
+}
+
+// integer types are rounded, NumCast alone truncates
+fn from_f64_rounded<T: StorableNum>(v: f64) -> Option<T> {
+    if T::from(0.5f64) == Some(T::zero()) {
+        T::from(v.round())
+    } else {
+        T::from(v)
     }
 }
 
 pub trait BlockWritable: StorableNum {
-    fn write_to_block(block: &mut SizedBlock, batch: &WriteBatch<Self>);
+    fn write_to_block(block: &mut SizedBlock, batch: &WriteBatch<Self>) -> WriteStats;
     fn new_sized_block(len: usize) -> SizedBlock;
+    fn block_data(block: &SizedBlock) -> (&BlockMeta<Self>, &[Self], &[Quality]);
+    fn block_data_mut(
+        block: &mut SizedBlock,
+    ) -> (&mut BlockMeta<Self>, &mut [Self], &mut [Quality]);
+    fn into_value_vec(vals: Vec<Self>) -> ValueVec;
 }
 
 macro_rules! impl_block_data_type {
-    ($type:ty, $variant:ident) => {
+    ($type:ty, $variant:ident, $vec_variant:ident) => {
         impl BlockWritable for $type {
-            fn write_to_block(block: &mut SizedBlock, batch: &WriteBatch<Self>) {
+            fn write_to_block(block: &mut SizedBlock, batch: &WriteBatch<Self>) -> WriteStats {
                 match block {
                     SizedBlock::$variant(block_meta, vals, qs) => {
-                        let bl_start =
-                            helpers::get_block_start_as_offset(batch.series, batch.block_id.0);
-
-                        for i in 0..batch.ts.len() {
-                            let idx =
-                                helpers::get_sample_offset(batch.series, batch.ts[i] - bl_start)
-                                    as usize;
-
-                            vals[idx] = batch.vals[i];
-                            qs[idx] = batch.qs[i];
-                        }
+                        let stats = write_samples(batch, vals, qs);
                         // TODO: do running stats instead of full recalc
                         block_meta.recalc_block_data_full(vals, qs);
+                        stats
                     }
                     other => {
                         unreachable!(
@@ -512,26 +659,124 @@ macro_rules! impl_block_data_type {
                     vec![Quality::MISSING; len],
                 )
             }
+
+            fn block_data(block: &SizedBlock) -> (&BlockMeta<Self>, &[Self], &[Quality]) {
+                match block {
+                    SizedBlock::$variant(block_meta, vals, qs) => (block_meta, vals, qs),
+                    other => {
+                        unreachable!(
+                            "Type Mismatch in block read: Expected {}, got {}",
+                            stringify!($variant),
+                            std::any::type_name_of_val(&other)
+                        );
+                    }
+                }
+            }
+
+            fn block_data_mut(
+                block: &mut SizedBlock,
+            ) -> (&mut BlockMeta<Self>, &mut [Self], &mut [Quality]) {
+                match block {
+                    SizedBlock::$variant(block_meta, vals, qs) => (block_meta, vals, qs),
+                    other => {
+                        unreachable!(
+                            "Type Mismatch in block write: Expected {}, got {}",
+                            stringify!($variant),
+                            std::any::type_name_of_val(&other)
+                        );
+                    }
+                }
+            }
+
+            fn into_value_vec(vals: Vec<Self>) -> ValueVec {
+                ValueVec::$vec_variant(vals)
+            }
         }
     };
 }
 
-impl_block_data_type!(f32, F32Block);
-impl_block_data_type!(f64, F64Block);
-impl_block_data_type!(i32, I32Block);
-impl_block_data_type!(i64, I64Block);
-impl_block_data_type!(u32, U32Block);
-impl_block_data_type!(u64, U64Block);
-impl_block_data_type!(u8, U8Block);
+impl_block_data_type!(f32, F32Block, F32);
+impl_block_data_type!(f64, F64Block, F64);
+impl_block_data_type!(i32, I32Block, I32);
+impl_block_data_type!(i64, I64Block, I64);
+impl_block_data_type!(u32, U32Block, U32);
+impl_block_data_type!(u64, U64Block, U64);
+impl_block_data_type!(u8, U8Block, Enum);
 
 impl SizedBlock {
-    pub fn write<T: BlockWritable>(&mut self, batch: &WriteBatch<T>) {
-        T::write_to_block(self, batch);
+    pub fn write<T: BlockWritable>(&mut self, batch: &WriteBatch<T>) -> WriteStats {
+        T::write_to_block(self, batch)
     }
 
     pub fn new<T: BlockWritable>(len: usize) -> SizedBlock {
         T::new_sized_block(len)
     }
+
+    // a block without samples
+    pub fn empty(stype: StorageType, len: usize) -> SizedBlock {
+        match stype {
+            StorageType::Float32 => f32::new_sized_block(len),
+            StorageType::Float64 => f64::new_sized_block(len),
+            StorageType::Int32 => i32::new_sized_block(len),
+            StorageType::Int64 => i64::new_sized_block(len),
+            StorageType::UInt32 => u32::new_sized_block(len),
+            StorageType::UInt64 => u64::new_sized_block(len),
+            StorageType::Enumeration => u8::new_sized_block(len),
+        }
+    }
+
+    pub fn recalc_meta(&mut self) {
+        match self {
+            SizedBlock::F32Block(meta, vals, qs) => meta.recalc_block_data_full(vals, qs),
+            SizedBlock::F64Block(meta, vals, qs) => meta.recalc_block_data_full(vals, qs),
+            SizedBlock::I32Block(meta, vals, qs) => meta.recalc_block_data_full(vals, qs),
+            SizedBlock::I64Block(meta, vals, qs) => meta.recalc_block_data_full(vals, qs),
+            SizedBlock::U32Block(meta, vals, qs) => meta.recalc_block_data_full(vals, qs),
+            SizedBlock::U64Block(meta, vals, qs) => meta.recalc_block_data_full(vals, qs),
+            SizedBlock::U8Block(meta, vals, qs) => meta.recalc_block_data_full(vals, qs),
+        }
+    }
+
+    // marks all non missing samples in `slots` as manually deleted and recalculates the block
+    // meta, returns how many were marked
+    pub fn mark_deleted<T: BlockWritable>(&mut self, slots: Range<usize>) -> u64 {
+        let (block_meta, vals, qs) = T::block_data_mut(self);
+        let mut deleted = 0;
+
+        for q in &mut qs[slots] {
+            if !q.is_missing() {
+                *q = Quality::DELETED;
+                deleted += 1;
+            }
+        }
+
+        if deleted > 0 {
+            block_meta.recalc_block_data_full(vals, qs);
+        }
+        deleted
+    }
+
+    // copies all non missing samples of `top` into this block and recalculates the block meta.
+    // deletions in `top` are copied as well, they hide the samples below.
+    // both blocks must have the same type and length.
+    pub fn overlay<T: BlockWritable>(&mut self, top: &SizedBlock) {
+        let (_, top_vals, top_qs) = T::block_data(top);
+        let (block_meta, vals, qs) = T::block_data_mut(self);
+        assert_eq!(
+            qs.len(),
+            top_qs.len(),
+            "overlay of blocks with different lengths"
+        );
+
+        for i in 0..top_qs.len() {
+            if !top_qs[i].is_missing() || top_qs[i].is_deleted() {
+                vals[i] = top_vals[i];
+                qs[i] = top_qs[i];
+            }
+        }
+
+        block_meta.recalc_block_data_full(vals, qs);
+    }
 }
 
 #[repr(u64)]
@@ -586,6 +831,46 @@ pub struct BlockLength(pub NonZero<u64>);
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct SampleLength(pub NonZero<u64>);
 
+// how timestamps between the slots of the fixed grid are mapped onto it
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlignPolicy {
+    // start of the slot the timestamp falls into
+    #[default]
+    Floor,
+    // closest slot start, halfway rounds up
+    Nearest,
+    // only timestamps exactly on a slot start are accepted
+    Reject,
+}
+
+// how a sample is resolved if its slot is already written, by the same batch or earlier ones
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    #[default]
+    LastWins,
+    FirstWins,
+    // the sample with the better quality wins, last wins on equal quality
+    BetterQuality,
+    // mean of all samples of the slot with the worst of their qualities
+    Average,
+    Reject,
+}
+
+// what happens to NaN and +-inf values written to a float series
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NonFinitePolicy {
+    #[default]
+    Reject,
+    // stored as a bad sample with value 0
+    StoreBad,
+}
+
+// default of `SeriesMeta::max_future_ms`, one day
+pub const DEFAULT_MAX_FUTURE_MS: u64 = 24 * 60 * 60 * 1000;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Label {
     pub name: String,
@@ -614,6 +899,21 @@ pub struct SeriesMeta {
     pub first_block: BlockNumber,
     pub last_block: BlockNumber,
     pub labels: Vec<Label>,
+    // IANA timezone name, used for aggregations in series-local time
+    pub timezone: String,
+    #[serde(default)]
+    pub alignment: AlignPolicy,
+    #[serde(default)]
+    pub collision: CollisionPolicy,
+    #[serde(default)]
+    pub non_finite: NonFinitePolicy,
+    // samples later than now + max_future_ms are rejected, 0 disables the check
+    #[serde(default = "default_max_future_ms")]
+    pub max_future_ms: u64,
+}
+
+fn default_max_future_ms() -> u64 {
+    DEFAULT_MAX_FUTURE_MS
 }
 
 #[derive(Debug)]
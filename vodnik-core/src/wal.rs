@@ -59,10 +59,30 @@ pub enum WalEntry<T: StorableNum> {
         series: SeriesId,
         block: BlockNumber,
     },
+    // same layout as Write, the samples replace the stored ones regardless of the
+    // collision policy of the series
+    Overwrite {
+        block: BlockNumber,
+        qs: Vec<Quality>,
+        series: SeriesId,
+        ts: Vec<u64>,
+        tx: TxId,
+        vals: Vec<T>,
+    },
+    // samples of the block in [from, to) are marked as manually deleted
+    Delete {
+        tx: TxId,
+        series: SeriesId,
+        block: BlockNumber,
+        from: u64,
+        to: u64,
+    },
 }
 
 pub const TAG_WRITE: u8 = 1;
 pub const TAG_FLUSH: u8 = 2;
+pub const TAG_DELETE: u8 = 3;
+pub const TAG_OVERWRITE: u8 = 4;
 
 impl<T: StorableNum> WalEntry<T> {
     pub fn write(&self, bytes: &mut [u8]) -> Result<usize, WalError> {
@@ -81,8 +101,20 @@ impl<T: StorableNum> WalEntry<T> {
                 ts,
                 vals,
                 qs,
+            }
+            | WalEntry::Overwrite {
+                tx,
+                series,
+                block,
+                ts,
+                vals,
+                qs,
             } => {
-                cursor.write_u8(TAG_WRITE);
+                let tag = match self {
+                    WalEntry::Overwrite { .. } => TAG_OVERWRITE,
+                    _ => TAG_WRITE,
+                };
+                cursor.write_u8(tag);
 
                 cursor.write_u64(tx.0);
                 cursor.write_u64(series.0.get());
@@ -107,6 +139,20 @@ impl<T: StorableNum> WalEntry<T> {
                 cursor.write_u64(series.0.get());
                 cursor.write_u64(block.0);
             }
+            WalEntry::Delete {
+                tx,
+                series,
+                block,
+                from,
+                to,
+            } => {
+                cursor.write_u8(TAG_DELETE);
+                cursor.write_u64(tx.0);
+                cursor.write_u64(series.0.get());
+                cursor.write_u64(block.0);
+                cursor.write_u64(*from);
+                cursor.write_u64(*to);
+            }
         }
 
         Ok(cursor.pos)
@@ -122,7 +168,7 @@ impl<T: StorableNum> WalEntry<T> {
         let tag = cursor.read_u8();
 
         match tag {
-            TAG_WRITE => {
+            TAG_WRITE | TAG_OVERWRITE => {
                 let tx = TxId(cursor.read_u64());
                 let series = SeriesId(
                     NonZero::new(cursor.read_u64())
@@ -147,6 +193,16 @@ impl<T: StorableNum> WalEntry<T> {
                     qs.push(Quality(cursor.read_u8()));
                 }
 
+                if tag == TAG_OVERWRITE {
+                    return Ok(WalEntry::Overwrite {
+                        tx,
+                        series,
+                        block,
+                        ts,
+                        vals,
+                        qs,
+                    });
+                }
                 Ok(WalEntry::Write {
                     tx,
                     series,
@@ -166,13 +222,31 @@ impl<T: StorableNum> WalEntry<T> {
 
                 Ok(WalEntry::Flush { tx, series, block })
             }
+            TAG_DELETE => {
+                let tx = TxId(cursor.read_u64());
+                let series = SeriesId(
+                    NonZero::new(cursor.read_u64())
+                        .ok_or(WalError::Serialization("0 series id".into()))?,
+                );
+                let block = BlockNumber(cursor.read_u64());
+                let from = cursor.read_u64();
+                let to = cursor.read_u64();
+
+                Ok(WalEntry::Delete {
+                    tx,
+                    series,
+                    block,
+                    from,
+                    to,
+                })
+            }
             _ => Err(WalError::Serialization(format!("Unknown tag: {}", tag))),
         }
     }
 
     pub fn storage_size_bytes(&self) -> usize {
         match self {
-            WalEntry::Write { qs, ts, vals, .. } => {
+            WalEntry::Write { qs, ts, vals, .. } | WalEntry::Overwrite { qs, ts, vals, .. } => {
                 size_of::<u8>() // Tag 
                     + size_of::<u64>() // block
                     + size_of::<u64>() // series
@@ -186,6 +260,10 @@ impl<T: StorableNum> WalEntry<T> {
                 // tx + series + block
                 size_of::<u8>() + size_of::<u64>() + size_of::<u64>() + size_of::<u64>()
             }
+            WalEntry::Delete { .. } => {
+                // tx + series + block + from + to
+                size_of::<u8>() + 5 * size_of::<u64>()
+            }
         }
     }
 }
@@ -1,30 +1,61 @@
 use std::fmt::Display;
 
 use axum::{
-    Router,
+    Json, Router,
     http::StatusCode,
     response::{IntoResponse, Response},
-    routing::{delete, get, patch, post},
+    routing::{delete, get, patch, post, put},
 };
+use serde::Serialize;
 use thiserror::Error;
 use tracing::{error, warn};
-use vodnik_core::wal::WalError;
+use vodnik_core::{api::SampleRejection, wal::WalError};
 
 use crate::{
     AppState,
-    crud::{create_series, delete_series, read_series, update_series},
-    ingest::batch_ingest,
+    crud::{create_series, delete_series, list_series, read_series, update_series},
+    edit::{delete_range, list_audit, overwrite_range},
+    gc::run_gc,
+    ingest::{batch_ingest, influx::influx_write, multi_batch_ingest, stream::stream_ingest},
     meta::{MetaStoreError, block::BlockMetaStoreError},
-    query::read_single_block,
+    operation::revert_operation,
+    query::{
+        aggregate::{aggregate, buckets, multi_aggregate, multi_buckets},
+        integral::integral,
+        multi_read_range,
+        opc::opc_aggregate,
+        point::value_at,
+        read_range, read_single_block,
+    },
 };
 
 pub(crate) fn routes() -> Router<AppState> {
     Router::new()
         .route("/batch", post(batch_ingest))
+        .route("/batch/multi", post(multi_batch_ingest))
+        .route("/batch/stream", post(stream_ingest))
+        // InfluxDB line protocol, v1 and v2 write paths
+        .route("/write", post(influx_write))
+        .route("/api/v2/write", post(influx_write))
         .route("/series", post(create_series))
+        .route("/series", get(list_series))
         .route("/series/{id}", get(read_series))
         .route("/series/{id}", patch(update_series))
         .route("/series/{id}", delete(delete_series))
+        .route("/series/{id}/data", get(read_range))
+        .route("/series/{id}/data", delete(delete_range))
+        .route("/series/{id}/data", put(overwrite_range))
+        .route("/series/{id}/audit", get(list_audit))
+        .route("/series/{id}/aggregate", get(aggregate))
+        .route("/series/{id}/buckets", get(buckets))
+        .route("/series/{id}/opc", get(opc_aggregate))
+        .route("/series/{id}/integral", get(integral))
+        .route("/series/{id}/value", get(value_at))
+        .route("/query/data", post(multi_read_range))
+        .route("/query/aggregate", post(multi_aggregate))
+        .route("/query/buckets", post(multi_buckets))
+        .route("/operations/{op}/revert", post(revert_operation))
+        .route("/gc", post(run_gc))
         .route(
             "/series/{series_id}/block/{block_id}",
             get(read_single_block),
@@ -45,6 +76,8 @@ pub enum ApiError {
     Internal,
     #[error("server busy")]
     ResourceLocked,
+    #[error("{} samples rejected", .0.len())]
+    Rejected(Vec<SampleRejection>),
 }
 
 pub(crate) fn as_internal_err<E: Display>(err: E) -> ApiError {
@@ -52,25 +85,41 @@ pub(crate) fn as_internal_err<E: Display>(err: E) -> ApiError {
     ApiError::Internal
 }
 
+impl ApiError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::ResourceLocked => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Rejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         match self {
-            ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-            ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
-            ApiError::Conflict(msg) => (StatusCode::CONFLICT, msg),
-            ApiError::Unprocessable(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
-            ApiError::Internal => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "internal server error".into(),
-            ),
-            ApiError::ResourceLocked => {
-                (StatusCode::SERVICE_UNAVAILABLE, "server busy".to_string())
+            ApiError::Rejected(rejections) => {
+                let body = Json(RejectionBody {
+                    error: format!("{} samples rejected", rejections.len()),
+                    rejections,
+                });
+                (StatusCode::UNPROCESSABLE_ENTITY, body).into_response()
             }
+            _ => (self.status(), self.to_string()).into_response(),
         }
-        .into_response()
     }
 }
 
+#[derive(Serialize)]
+struct RejectionBody {
+    error: String,
+    rejections: Vec<SampleRejection>,
+}
+
 impl From<MetaStoreError> for ApiError {
     fn from(err: MetaStoreError) -> Self {
         match err {
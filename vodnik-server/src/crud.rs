@@ -3,21 +3,25 @@ use std::num::NonZero;
 use crate::{
     AppState,
     api::{ApiError, as_internal_err},
-    meta::MetaStoreError,
+    meta::{
+        MetaStoreError,
+        label::{LabelMatcher, LabelOp, MatchMode},
+        store::{Page, SortBy},
+    },
 };
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
 };
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use vodnik_core::{
-    helpers::{derive_block_size, duration},
+    helpers::{derive_block_size, duration, parse_timezone},
     meta::{
-        BlockLength, BlockNumber, Label, SampleLength, SeriesId, SeriesMeta, StorageType,
-        TimeResolution,
+        AlignPolicy, BlockLength, BlockNumber, CollisionPolicy, DEFAULT_MAX_FUTURE_MS, Label,
+        NonFinitePolicy, SampleLength, SeriesId, SeriesMeta, StorageType, TimeResolution,
     },
 };
 
@@ -29,6 +33,10 @@ pub enum CrudError {
     SampleBlockDurationMismatch,
     #[error("invalid series name: '{0}'. validity: /^[a-zA-Z][a-zA-Z0-9_]*$/")]
     InvalidSeriesName(String),
+    #[error("{0}")]
+    InvalidTimezone(String),
+    #[error("collision policy 'average' isn't supported for enumerations")]
+    AverageOfEnumeration,
 }
 
 impl From<CrudError> for ApiError {
@@ -36,6 +44,8 @@ impl From<CrudError> for ApiError {
         match err {
             CrudError::SampleBlockDurationMismatch => ApiError::BadRequest(err.to_string()),
             CrudError::InvalidSeriesName(_) => ApiError::BadRequest(err.to_string()),
+            CrudError::InvalidTimezone(_) => ApiError::BadRequest(err.to_string()),
+            CrudError::AverageOfEnumeration => ApiError::BadRequest(err.to_string()),
         }
     }
 }
@@ -49,6 +59,16 @@ pub struct CreateSeries {
     pub sample_length: SampleLength,
     pub sample_resolution: TimeResolution,
     pub labels: Vec<Label>,
+    // IANA timezone name, defaults to UTC
+    pub timezone: Option<String>,
+    // off-grid timestamps, defaults to floor
+    pub alignment: Option<AlignPolicy>,
+    // samples in an already written slot, defaults to last wins
+    pub collision: Option<CollisionPolicy>,
+    // NaN and +-inf values, defaults to reject
+    pub non_finite: Option<NonFinitePolicy>,
+    // how far samples may lie in the future, defaults to one day. 0 disables the check
+    pub max_future_ms: Option<u64>,
 }
 
 impl From<&CreateSeries> for SeriesMeta {
@@ -73,11 +93,20 @@ impl From<&CreateSeries> for SeriesMeta {
             first_block: BlockNumber(0),
             last_block: BlockNumber(0),
             labels: value.labels.clone(),
+            timezone: value
+                .timezone
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TIMEZONE.to_string()),
+            alignment: value.alignment.unwrap_or_default(),
+            collision: value.collision.unwrap_or_default(),
+            non_finite: value.non_finite.unwrap_or_default(),
+            max_future_ms: value.max_future_ms.unwrap_or(DEFAULT_MAX_FUTURE_MS),
         }
     }
 }
 
 const RE_NAME: &str = "^[a-zA-Z][a-zA-Z0-9_]*$";
+const DEFAULT_TIMEZONE: &str = "UTC";
 impl CreateSeries {
     pub fn validate(&self) -> Result<(), ApiError> {
         if let (Some(block_resolution), Some(block_length)) =
@@ -93,6 +122,14 @@ impl CreateSeries {
 
         validate_series_name(self.name.as_str())?;
 
+        if let Some(tz) = &self.timezone {
+            validate_timezone(tz)?;
+        }
+
+        if let Some(collision) = self.collision {
+            validate_collision(self.storage_type, collision)?;
+        }
+
         Ok(())
     }
 }
@@ -104,6 +141,18 @@ fn validate_series_name(name: &str) -> Result<(), ApiError> {
     })
 }
 
+fn validate_timezone(tz: &str) -> Result<(), ApiError> {
+    parse_timezone(tz).map_err(CrudError::InvalidTimezone)?;
+    Ok(())
+}
+
+fn validate_collision(stype: StorageType, collision: CollisionPolicy) -> Result<(), ApiError> {
+    if matches!(stype, StorageType::Enumeration) && collision == CollisionPolicy::Average {
+        return Err(CrudError::AverageOfEnumeration.into());
+    }
+    Ok(())
+}
+
 pub fn into_api_error(e: MetaStoreError) -> ApiError {
     e.into()
 }
@@ -134,11 +183,23 @@ pub(crate) async fn read_series(
 pub struct UpdateSeries {
     pub name: Option<String>,
     pub labels: Option<Vec<Label>>,
+    pub timezone: Option<String>,
+    pub alignment: Option<AlignPolicy>,
+    pub collision: Option<CollisionPolicy>,
+    pub non_finite: Option<NonFinitePolicy>,
+    pub max_future_ms: Option<u64>,
 }
 
 impl UpdateSeries {
     fn validate(&self) -> Result<(), ApiError> {
-        if self.name.is_none() && self.labels.is_none() {
+        if self.name.is_none()
+            && self.labels.is_none()
+            && self.timezone.is_none()
+            && self.alignment.is_none()
+            && self.collision.is_none()
+            && self.non_finite.is_none()
+            && self.max_future_ms.is_none()
+        {
             return Err(ApiError::BadRequest("No changes to apply".to_string()));
         }
 
@@ -146,6 +207,10 @@ impl UpdateSeries {
             validate_series_name(name)?;
         }
 
+        if let Some(tz) = &self.timezone {
+            validate_timezone(tz)?;
+        }
+
         Ok(())
     }
 }
@@ -166,6 +231,27 @@ pub(crate) async fn update_series(
         series.labels = labels;
     }
 
+    if let Some(timezone) = update.timezone {
+        series.timezone = timezone;
+    }
+
+    if let Some(alignment) = update.alignment {
+        series.alignment = alignment;
+    }
+
+    if let Some(collision) = update.collision {
+        validate_collision(series.storage_type, collision)?;
+        series.collision = collision;
+    }
+
+    if let Some(non_finite) = update.non_finite {
+        series.non_finite = non_finite;
+    }
+
+    if let Some(max_future_ms) = update.max_future_ms {
+        series.max_future_ms = max_future_ms;
+    }
+
     state
         .meta_store
         .update(&series)
@@ -176,6 +262,169 @@ pub(crate) async fn update_series(
     Ok(Json(series))
 }
 
+// selects series by their labels. `labels` is a shorthand for `eq` matchers
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LabelSelector {
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    #[serde(default)]
+    pub matchers: Vec<LabelMatcher>,
+    #[serde(default)]
+    pub mode: MatchMode,
+}
+
+impl LabelSelector {
+    fn all_matchers(&self) -> Vec<LabelMatcher> {
+        self.labels
+            .iter()
+            .map(|l| LabelMatcher {
+                name: l.name.clone(),
+                op: LabelOp::Eq,
+                value: l.value.clone(),
+            })
+            .chain(self.matchers.iter().cloned())
+            .collect()
+    }
+
+    fn validate(&self) -> Result<(), ApiError> {
+        if self.labels.is_empty() && self.matchers.is_empty() {
+            return Err(ApiError::BadRequest(
+                "label selector without labels".to_string(),
+            ));
+        }
+
+        for m in &self.matchers {
+            if m.op == LabelOp::Re {
+                Regex::new(&m.value).map_err(|e| {
+                    ApiError::BadRequest(format!("invalid regex for label '{}': {e}", m.name))
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn resolve(&self, state: &AppState) -> Result<Vec<SeriesMeta>, ApiError> {
+        self.validate()?;
+        state
+            .meta_store
+            .find(&self.all_matchers(), self.mode)
+            .await
+            .map_err(into_api_error)
+    }
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 100;
+const MAX_PAGE_SIZE: u64 = 1000;
+
+// GET /series?label=unit:celsius&label_re=line:^l[12]$&label_exists=plant&mode=all&sort=name
+//   label=name:value, label_ne=name:value, label_re=name:regex, label_exists=name
+// matchers may be repeated, so the query is parsed from the raw key value pairs.
+#[derive(Debug)]
+pub struct ListSeries {
+    pub selector: Option<LabelSelector>,
+    pub page: Page,
+}
+
+impl TryFrom<Vec<(String, String)>> for ListSeries {
+    type Error = ApiError;
+
+    fn try_from(params: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let bad = |msg: String| ApiError::BadRequest(msg);
+        let num = |k: &str, v: &str| {
+            v.parse::<u64>()
+                .map_err(|_| bad(format!("'{k}' must be a non negative number")))
+        };
+        let matcher = |op: LabelOp, v: &str| {
+            let (name, value) = v
+                .split_once(':')
+                .ok_or_else(|| bad(format!("invalid label '{v}', expected name:value")))?;
+            Ok::<_, ApiError>(LabelMatcher {
+                name: name.to_string(),
+                op,
+                value: value.to_string(),
+            })
+        };
+
+        let mut selector = LabelSelector::default();
+        let mut page = Page {
+            sort: SortBy::default(),
+            descending: false,
+            limit: DEFAULT_PAGE_SIZE,
+            offset: 0,
+        };
+
+        for (k, v) in params {
+            match k.as_str() {
+                "label" => selector.matchers.push(matcher(LabelOp::Eq, &v)?),
+                "label_ne" => selector.matchers.push(matcher(LabelOp::Ne, &v)?),
+                "label_re" => selector.matchers.push(matcher(LabelOp::Re, &v)?),
+                "label_exists" => selector.matchers.push(LabelMatcher {
+                    name: v,
+                    op: LabelOp::Exists,
+                    value: String::new(),
+                }),
+                "mode" => {
+                    selector.mode = match v.as_str() {
+                        "all" => MatchMode::All,
+                        "any" => MatchMode::Any,
+                        _ => return Err(bad(format!("invalid mode '{v}', expected all|any"))),
+                    }
+                }
+                "sort" => {
+                    page.sort = match v.as_str() {
+                        "id" => SortBy::Id,
+                        "name" => SortBy::Name,
+                        _ => return Err(bad(format!("invalid sort '{v}', expected id|name"))),
+                    }
+                }
+                "order" => {
+                    page.descending = match v.as_str() {
+                        "asc" => false,
+                        "desc" => true,
+                        _ => return Err(bad(format!("invalid order '{v}', expected asc|desc"))),
+                    }
+                }
+                "limit" => page.limit = num(&k, &v)?.clamp(1, MAX_PAGE_SIZE),
+                "offset" => page.offset = num(&k, &v)?,
+                _ => return Err(bad(format!("unknown query parameter '{k}'"))),
+            }
+        }
+
+        let selector = (!selector.matchers.is_empty()).then_some(selector);
+        Ok(ListSeries { selector, page })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesPage {
+    // number of series matching the selector
+    pub total: u64,
+    pub series: Vec<SeriesMeta>,
+}
+
+pub(crate) async fn list_series(
+    State(state): State<AppState>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Json<SeriesPage>, ApiError> {
+    let query = ListSeries::try_from(params)?;
+
+    let (matchers, mode) = match &query.selector {
+        Some(selector) => {
+            selector.validate()?;
+            (selector.all_matchers(), selector.mode)
+        }
+        None => (vec![], MatchMode::All),
+    };
+
+    let (series, total) = state
+        .meta_store
+        .find_page(&matchers, mode, &query.page)
+        .await
+        .map_err(into_api_error)?;
+
+    Ok(Json(SeriesPage { total, series }))
+}
+
 pub(crate) async fn delete_series(
     State(state): State<AppState>,
     Path(id): Path<SeriesId>,
@@ -0,0 +1,465 @@
+use std::ops::Range;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+use tracing::{error, info};
+use vodnik_core::{
+    api::{
+        DeleteResult, OverwriteRequest, OverwriteResult, RejectReason, SampleRejection, ValueVec,
+        check_sample,
+    },
+    helpers,
+    meta::{
+        BlockNumber, BlockWritable, CollisionPolicy, Quality, SeriesId, SeriesMeta, SizedBlock,
+        StorageType, WriteBatch, WriteStats,
+    },
+    wal::{TxId, WalEntry},
+};
+
+use crate::{
+    AppState,
+    api::{ApiError, as_internal_err},
+    hot::ModifyResult,
+    ingest::{now_ms, write_flush_to_wal},
+    meta::{
+        audit::{self, NewAuditRecord},
+        into_api_error,
+    },
+    operation::{WithOperation, new_operation, with_operation},
+    persistence,
+    query::{RangeQuery, blocks_in_range},
+    wal::next_txid,
+};
+
+// marks all samples in [from, to) as manually deleted. the values are kept in the blocks,
+// reads and aggregations skip them like missing samples.
+pub(crate) async fn delete_range(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Query(range): Query<RangeQuery>,
+) -> Result<WithOperation<Json<DeleteResult>>, ApiError> {
+    range.validate()?;
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+
+    let op = new_operation();
+    let res = match series.storage_type {
+        StorageType::Float32 => delete_samples::<f32>(&state, &series, &range, &op).await,
+        StorageType::Float64 => delete_samples::<f64>(&state, &series, &range, &op).await,
+        StorageType::Int32 => delete_samples::<i32>(&state, &series, &range, &op).await,
+        StorageType::Int64 => delete_samples::<i64>(&state, &series, &range, &op).await,
+        StorageType::UInt32 => delete_samples::<u32>(&state, &series, &range, &op).await,
+        StorageType::UInt64 => delete_samples::<u64>(&state, &series, &range, &op).await,
+        StorageType::Enumeration => delete_samples::<u8>(&state, &series, &range, &op).await,
+    }?;
+
+    info!(
+        "deleted {} samples in {} blocks of series {}",
+        res.deleted, res.blocks, series.id
+    );
+    Ok(with_operation(op, Json(res)))
+}
+
+async fn delete_samples<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    range: &RangeQuery,
+    op: &str,
+) -> Result<DeleteResult, ApiError> {
+    let mut res = DeleteResult::default();
+
+    for block_ref in blocks_in_range::<T>(state, series, range.from, range.to, None).await? {
+        let tx = TxId(next_txid());
+        let deleted = delete_in_block::<T>(
+            state,
+            series,
+            block_ref.id,
+            range.from..range.to,
+            tx,
+            Some(op),
+            false,
+        )
+        .await?;
+        if deleted > 0 {
+            res.deleted += deleted;
+            res.blocks += 1;
+        }
+    }
+
+    Ok(res)
+}
+
+// marks the samples of one block in `range` as deleted, in the HotSet as well as in storage.
+// the stored block is rewritten copy-on-write. returns how many samples were marked.
+pub(crate) async fn delete_in_block<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    block: BlockNumber,
+    range: Range<u64>,
+    tx: TxId,
+    op: Option<&str>,
+    replay: bool,
+) -> Result<u64, ApiError> {
+    let Range {
+        start: from,
+        end: to,
+    } = range;
+    let slots = helpers::slots_in_range(series, block.0, from, to);
+    if slots.is_empty() {
+        return Ok(0);
+    }
+
+    if !replay {
+        let mut w_entry = WalEntry::<T>::Delete {
+            tx,
+            series: series.id,
+            block,
+            from,
+            to,
+        };
+        state
+            .wal
+            .lock()
+            .map_err(|_| ApiError::ResourceLocked)?
+            .write_entry(&mut w_entry)?;
+    }
+
+    // waits for a flush of the hot block, a change of the block in flight would be lost
+    let _guard = state.block_locks.lock(series.id, block).await;
+    let hot_deleted = modify_hot(
+        state
+            .hot
+            .delete::<T>(series.id, block, slots.clone(), tx, op),
+    )?;
+
+    // the hot block may only hold the samples written since the last restart, the stored
+    // version is rewritten as well
+    let cold_deleted = match persistence::read_block_from_storage(
+        &state.storage,
+        &state.block_meta,
+        series.id,
+        block,
+    )
+    .await
+    {
+        Ok(mut stored) => {
+            let deleted = stored.mark_deleted::<T>(slots);
+            if deleted > 0 {
+                persistence::flush_block(
+                    &state.storage,
+                    &state.block_meta,
+                    series.id,
+                    block,
+                    &stored,
+                    tx,
+                    op.as_slice(),
+                )
+                .await?;
+            }
+            deleted
+        }
+        Err(ApiError::NotFound(_)) => 0,
+        Err(e) => return Err(e),
+    };
+
+    // a deletion in the hot block is done once that block is flushed, its tx covers ours
+    if hot_deleted.is_none() && !replay {
+        _ = write_flush_to_wal::<u8>(state, tx, series.id, block).inspect_err(|e| error!("{e}"));
+    }
+
+    // samples usually live in only one of both, the hot block shadows the stored one
+    Ok(hot_deleted.unwrap_or_default().max(cold_deleted))
+}
+
+// replaces the values of single samples. the replaced block versions stay in storage and each
+// rewritten block gets an audit record.
+pub(crate) async fn overwrite_range(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Json(req): Json<OverwriteRequest>,
+) -> Result<WithOperation<Json<OverwriteResult>>, ApiError> {
+    req.validate()?;
+    if req.user.trim().is_empty() || req.reason.trim().is_empty() {
+        return Err(ApiError::BadRequest(
+            "'user' and 'reason' are required".to_string(),
+        ));
+    }
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+    req.vals.check_type(series.storage_type)?;
+
+    let OverwriteRequest {
+        ts,
+        vals,
+        user,
+        reason,
+    } = req;
+    let op = new_operation();
+    let res = match vals {
+        ValueVec::F32(vals) => {
+            overwrite_samples(&state, &series, &ts, &vals, &user, &reason, &op).await
+        }
+        ValueVec::F64(vals) => {
+            overwrite_samples(&state, &series, &ts, &vals, &user, &reason, &op).await
+        }
+        ValueVec::I32(vals) => {
+            overwrite_samples(&state, &series, &ts, &vals, &user, &reason, &op).await
+        }
+        ValueVec::I64(vals) => {
+            overwrite_samples(&state, &series, &ts, &vals, &user, &reason, &op).await
+        }
+        ValueVec::U32(vals) => {
+            overwrite_samples(&state, &series, &ts, &vals, &user, &reason, &op).await
+        }
+        ValueVec::U64(vals) => {
+            overwrite_samples(&state, &series, &ts, &vals, &user, &reason, &op).await
+        }
+        ValueVec::Enum(vals) => {
+            overwrite_samples(&state, &series, &ts, &vals, &user, &reason, &op).await
+        }
+    }?;
+
+    info!(
+        "{user} overwrote {} samples in {} blocks of series {}",
+        res.samples, res.blocks, series.id
+    );
+    Ok(with_operation(op, Json(res)))
+}
+
+async fn overwrite_samples<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    ts: &[u64],
+    vals: &[T],
+    user: &str,
+    reason: &str,
+    op: &str,
+) -> Result<OverwriteResult, ApiError> {
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+    let now = now_ms();
+
+    let mut rejections = vec![];
+    for i in 0..ts.len() {
+        let res = if !ts[i].is_multiple_of(sample_ms) {
+            Err(RejectReason::OffGrid)
+        } else if vals[i].to_f64().is_some_and(|v| !v.is_finite()) {
+            Err(RejectReason::NonFinite)
+        } else {
+            check_sample(series, now, ts[i], Quality::OVERWRITTEN)
+        };
+        if let Err(reason) = res {
+            rejections.push(SampleRejection { index: i, reason });
+        }
+    }
+    if !rejections.is_empty() {
+        return Err(ApiError::Rejected(rejections));
+    }
+
+    // overwritten samples always replace the stored ones
+    let series = SeriesMeta {
+        collision: CollisionPolicy::LastWins,
+        ..series.clone()
+    };
+    let qs = vec![Quality::OVERWRITTEN; ts.len()];
+    let mut res = OverwriteResult::default();
+
+    let mut start = 0;
+    while start < ts.len() {
+        let block = helpers::get_block_id(&series, ts[start]);
+        let end = ts[start..]
+            .iter()
+            .position(|t| helpers::get_block_id(&series, *t) != block)
+            .map_or(ts.len(), |n| start + n);
+
+        let batch = WriteBatch::new(
+            &series,
+            BlockNumber(block),
+            &ts[start..end],
+            &vals[start..end],
+            &qs[start..end],
+            TxId(next_txid()),
+        )
+        .with_op(Some(op));
+        let (previous_object_key, object_key, stats) =
+            overwrite_in_block(state, &batch, false).await?;
+
+        state
+            .audit
+            .insert(
+                NewAuditRecord {
+                    series_id: series.id,
+                    block_id: batch.block_id,
+                    from: ts[start],
+                    to: ts[end - 1] + sample_ms,
+                    samples: stats.samples,
+                    user,
+                    reason,
+                    previous_object_key,
+                    object_key,
+                    operation: op,
+                },
+                now_ms(),
+            )
+            .await
+            .map_err(as_internal_err)?;
+
+        res.samples += stats.samples;
+        res.blocks += 1;
+        start = end;
+    }
+
+    Ok(res)
+}
+
+// writes the batch into the HotSet and as a new block version into storage. returns the key of
+// the replaced version (None if the block wasn't stored before) and the key of the new one.
+pub(crate) async fn overwrite_in_block<T: BlockWritable>(
+    state: &AppState,
+    batch: &WriteBatch<'_, T>,
+    replay: bool,
+) -> Result<(Option<String>, String, WriteStats), ApiError> {
+    let (series, block) = (batch.series, batch.block_id);
+
+    if !replay {
+        let mut w_entry = WalEntry::Overwrite {
+            block,
+            qs: batch.qs.to_vec(),
+            series: series.id,
+            ts: batch.ts.to_vec(),
+            tx: batch.tx,
+            vals: batch.vals.to_vec(),
+        };
+        state
+            .wal
+            .lock()
+            .map_err(|_| ApiError::ResourceLocked)?
+            .write_entry(&mut w_entry)?;
+    }
+
+    // also waits for a flush of the hot block, the overwrite lands in memory after it
+    let _guard = state.block_locks.lock(series.id, block).await;
+    let hot_before = state.hot.get_block_with_operations(series.id, block);
+    let hot_stats = modify_hot(state.hot.overwrite(batch))?;
+
+    let stored = match persistence::read_block_from_storage(
+        &state.storage,
+        &state.block_meta,
+        series.id,
+        block,
+    )
+    .await
+    {
+        Ok(b) => Some(b),
+        Err(ApiError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    // the version being replaced. samples only held in memory are persisted first, under the
+    // operations that wrote them, so the original values are kept for hot blocks too. a revert
+    // of the overwrite goes back to it
+    let (previous, mut current) = match (stored, hot_before) {
+        (Some(mut stored), Some((hot, ops))) => {
+            stored.overlay::<T>(&hot);
+            let ops: Vec<&str> = ops.iter().map(String::as_str).collect();
+            let key = flush_version(state, series, block, &stored, batch.tx, &ops).await?;
+            (Some(key), stored)
+        }
+        (None, Some((hot, ops))) => {
+            let ops: Vec<&str> = ops.iter().map(String::as_str).collect();
+            let key = flush_version(state, series, block, &hot, batch.tx, &ops).await?;
+            (Some(key), hot)
+        }
+        (Some(stored), None) => (Some(T::block_data(&stored).0.object_key.clone()), stored),
+        (None, None) => (
+            None,
+            T::new_sized_block(helpers::get_block_length(series) as usize),
+        ),
+    };
+
+    let stats = current.write(batch);
+    let key = flush_version(
+        state,
+        series,
+        block,
+        &current,
+        batch.tx,
+        batch.op.as_slice(),
+    )
+    .await?;
+
+    // an overwrite of the hot block is done once that block is flushed, its tx covers ours
+    if hot_stats.is_none() && !replay {
+        _ = write_flush_to_wal::<u8>(state, batch.tx, series.id, block)
+            .inspect_err(|e| error!("{e}"));
+    }
+
+    Ok((previous, key, stats))
+}
+
+async fn flush_version(
+    state: &AppState,
+    series: &SeriesMeta,
+    block: BlockNumber,
+    version: &SizedBlock,
+    tx: TxId,
+    ops: &[&str],
+) -> Result<String, ApiError> {
+    persistence::flush_block(
+        &state.storage,
+        &state.block_meta,
+        series.id,
+        block,
+        version,
+        tx,
+        ops,
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    // only records overlapping [from, to), ms after UNIX EPOCH
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+pub(crate) async fn list_audit(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<audit::Model>>, ApiError> {
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+
+    let records = state
+        .audit
+        .list(series.id, query.from, query.to)
+        .await
+        .map_err(as_internal_err)?;
+    Ok(Json(records))
+}
+
+// result of a change to a hot block, None if the block isn't hot. callers hold the block lock,
+// a flush of the block has finished before, so it is never busy.
+fn modify_hot<R>(res: ModifyResult<R>) -> Result<Option<R>, ApiError> {
+    match res {
+        ModifyResult::Ok(res) => Ok(Some(res)),
+        ModifyResult::NotHot => Ok(None),
+        ModifyResult::Busy => {
+            error!("hot block is in flight while its block lock is held");
+            Err(ApiError::ResourceLocked)
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+use tracing::{error, info, warn};
+use ulid::Ulid;
+use vodnik_core::api::GcReport;
+
+use crate::{
+    AppState,
+    api::{ApiError, as_internal_err},
+    ingest::now_ms,
+};
+
+// TODO: settings!
+const GC_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+// prefix of all block objects, see `persistence::flush_block`
+const DATA_PREFIX: &str = "data/";
+
+#[derive(Clone, Copy, Debug)]
+pub struct GcConfig {
+    // younger objects are never collected, their block meta may not be written yet
+    pub grace: Duration,
+    // block versions older than this are dropped, None keeps all versions
+    pub version_retention: Option<Duration>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GcQuery {
+    // only report what would be deleted, versions aren't pruned
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = collect(&state, false).await {
+                error!("garbage collection failed: {e}");
+            }
+        }
+    });
+}
+
+// POST /gc, runs a garbage collection now
+pub(crate) async fn run_gc(
+    State(state): State<AppState>,
+    Query(query): Query<GcQuery>,
+) -> Result<Json<GcReport>, ApiError> {
+    collect(&state, query.dry_run).await.map(Json)
+}
+
+// deletes block objects that neither a block, a kept block version nor an audit record points
+// to. these are versions dropped by the retention policy and objects of failed flushes.
+pub(crate) async fn collect(state: &AppState, dry_run: bool) -> Result<GcReport, ApiError> {
+    let now = now_ms();
+    let mut report = GcReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    if let Some(retention) = state.gc.version_retention
+        && !dry_run
+    {
+        let before = now.saturating_sub(retention.as_millis() as u64);
+        report.versions_pruned = state.block_meta.prune_versions(before).await?;
+    }
+
+    // listed before the references are read, an object flushed meanwhile is either referenced
+    // already or within the grace period
+    let grace_ms = state.gc.grace.as_millis() as u64;
+    let mut candidates = vec![];
+    let mut lister = state
+        .storage
+        .lister_with(DATA_PREFIX)
+        .recursive(true)
+        .await
+        .map_err(as_internal_err)?;
+    while let Some(entry) = lister.try_next().await.map_err(as_internal_err)? {
+        if !entry.metadata().is_file() {
+            continue;
+        }
+        report.objects += 1;
+
+        match written_at(entry.path()) {
+            Some(ts) if now.saturating_sub(ts) >= grace_ms => {
+                candidates.push(entry.path().to_string())
+            }
+            Some(_) => {}
+            None => warn!("gc: skipping unknown object {}", entry.path()),
+        }
+    }
+
+    let mut referenced = state.block_meta.referenced_keys().await?;
+    referenced.extend(
+        state
+            .audit
+            .referenced_keys()
+            .await
+            .map_err(as_internal_err)?,
+    );
+
+    for key in candidates.iter().filter(|k| !referenced.contains(*k)) {
+        let size = state
+            .storage
+            .stat(key)
+            .await
+            .map_err(as_internal_err)?
+            .content_length();
+        if !dry_run {
+            state.storage.delete(key).await.map_err(as_internal_err)?;
+        }
+        report.deleted += 1;
+        report.bytes_reclaimed += size;
+    }
+
+    info!(
+        "gc{}: {} of {} objects unreferenced, {} bytes reclaimed, {} versions pruned",
+        if dry_run { " (dry run)" } else { "" },
+        report.deleted,
+        report.objects,
+        report.bytes_reclaimed,
+        report.versions_pruned
+    );
+    Ok(report)
+}
+
+// block objects are named {block_id}_{ulid}.blk, the ulid holds the time of the write
+fn written_at(key: &str) -> Option<u64> {
+    let name = key.rsplit('/').next()?.strip_suffix(".blk")?;
+    let (_, id) = name.split_once('_')?;
+    Ulid::from_string(id).ok().map(|id| id.timestamp_ms())
+}
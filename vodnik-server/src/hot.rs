@@ -1,4 +1,7 @@
-use std::{collections::HashMap, ops::Range};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    ops::Range,
+};
 
 use dashmap::DashMap;
 use tracing::{debug, info, trace};
@@ -6,7 +9,7 @@ use vodnik_core::api::ValueVec;
 use vodnik_core::helpers;
 use vodnik_core::meta::{
     BlockMeta, BlockNumber, BlockWritable, Quality, SeriesId, SeriesMeta, SizedBlock, StorageType,
-    WriteBatch,
+    WriteBatch, WriteStats,
 };
 use vodnik_core::wal::TxId;
 
@@ -14,18 +17,31 @@ use vodnik_core::wal::TxId;
 struct HotData {
     live: Option<(TxId, SizedBlock)>,
     flushing: HashMap<BlockNumber, (TxId, SizedBlock)>,
+    // flushing blocks currently being written to storage. they stay readable until the flush is done
+    in_flight: HashSet<BlockNumber>,
     live_id: Option<BlockNumber>,
+    // operations that wrote into the live and flushing blocks, recorded on the flushed versions
+    operations: HashMap<BlockNumber, BTreeSet<String>>,
 }
 #[derive(Debug)]
 pub(crate) enum WriteResult {
     Ok {
         live: BlockNumber,
         flushing: Vec<BlockNumber>,
+        stats: WriteStats,
     },
     Busy,
     NeedsColdStore,
 }
 
+// result of changing a block in place, see `HotSet::delete` and `HotSet::overwrite`
+#[derive(Debug)]
+pub(crate) enum ModifyResult<R> {
+    Ok(R),
+    Busy,
+    NotHot,
+}
+
 impl HotData {
     fn write_into_block<T: BlockWritable>(&mut self, batch: &WriteBatch<T>) -> WriteResult {
         debug!(
@@ -63,7 +79,8 @@ impl HotData {
         };
 
         // write to the block
-        current.write::<T>(batch);
+        let stats = current.write::<T>(batch);
+        self.add_operation(batch.block_id, batch.op);
         // TODO: handle out of order better
         let tx = TxId(batch.tx.0.max(tx.0));
         // State Restore
@@ -72,7 +89,42 @@ impl HotData {
 
         WriteResult::Ok {
             live: self.live_id.expect("No live_id after write"),
-            flushing: self.flushing.keys().copied().collect(),
+            flushing: self
+                .flushing
+                .keys()
+                .filter(|b| !self.in_flight.contains(b))
+                .copied()
+                .collect(),
+            stats,
+        }
+    }
+
+    // applies `f` to the live or flushing block, the block keeps the larger tx of both
+    fn modify_block<R>(
+        &mut self,
+        block: BlockNumber,
+        tx: TxId,
+        op: Option<&str>,
+        f: impl FnOnce(&mut SizedBlock) -> R,
+    ) -> ModifyResult<R> {
+        let entry = if self.live_id == Some(block) {
+            self.live.as_mut()
+        } else if self.in_flight.contains(&block) {
+            // the flush would persist the block without the change. doesn't happen while the
+            // block lock is held, see `BlockLocks`
+            return ModifyResult::Busy;
+        } else {
+            self.flushing.get_mut(&block)
+        };
+
+        match entry {
+            Some((block_tx, current)) => {
+                let res = f(current);
+                *block_tx = TxId(block_tx.0.max(tx.0));
+                self.add_operation(block, op);
+                ModifyResult::Ok(res)
+            }
+            None => ModifyResult::NotHot,
         }
     }
 
@@ -81,8 +133,29 @@ impl HotData {
         self.flushing.insert(self.live_id.unwrap(), live);
     }
 
-    fn take_flushing_block(&mut self, block: BlockNumber) -> Option<(TxId, SizedBlock)> {
-        self.flushing.remove(&block)
+    fn add_operation(&mut self, block: BlockNumber, op: Option<&str>) {
+        if let Some(op) = op {
+            let ops = self.operations.entry(block).or_default();
+            if !ops.contains(op) {
+                ops.insert(op.to_string());
+            }
+        }
+    }
+
+    fn block_operations(&self, block: BlockNumber) -> Vec<String> {
+        self.operations
+            .get(&block)
+            .map(|ops| ops.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn take_flushing_block(
+        &mut self,
+        block: BlockNumber,
+    ) -> Option<(TxId, SizedBlock, Vec<String>)> {
+        let (tx, b) = self.flushing.remove(&block)?;
+        let ops = self.operations.remove(&block).unwrap_or_default();
+        Some((tx, b, ops.into_iter().collect()))
     }
 }
 
@@ -107,15 +180,15 @@ impl HotSet {
 
     pub(crate) fn take_all_blocks(
         &self,
-        buff: &mut Vec<(SeriesId, TxId, BlockNumber, SizedBlock)>,
+        buff: &mut Vec<(SeriesId, TxId, BlockNumber, SizedBlock, Vec<String>)>,
     ) {
         for mut k in self.data.iter_mut() {
             let series = k.key().clone();
             k.flush_live();
             let blocks: Vec<BlockNumber> = k.flushing.keys().copied().collect();
             for b in blocks {
-                if let Some((tx, block)) = k.value_mut().take_flushing_block(b) {
-                    buff.push((series, tx, b, block));
+                if let Some((tx, block, ops)) = k.value_mut().take_flushing_block(b) {
+                    buff.push((series, tx, b, block, ops));
                 }
             }
         }
@@ -130,20 +203,115 @@ impl HotSet {
         }
     }
 
-    pub(crate) fn take_flushing_block(
+    // returns a copy of the live or flushing block, if the block is still held in memory
+    pub(crate) fn get_block(&self, series: SeriesId, block: BlockNumber) -> Option<SizedBlock> {
+        self.get_block_with_operations(series, block)
+            .map(|(b, _)| b)
+    }
+
+    // like `get_block`, together with the operations that wrote into the block
+    pub(crate) fn get_block_with_operations(
+        &self,
+        series: SeriesId,
+        block: BlockNumber,
+    ) -> Option<(SizedBlock, Vec<String>)> {
+        let hd = self.data.get(&series)?;
+
+        let b = if hd.live_id == Some(block)
+            && let Some((_, live)) = &hd.live
+        {
+            live.clone()
+        } else {
+            hd.flushing.get(&block).map(|(_, b)| b.clone())?
+        };
+        Some((b, hd.block_operations(block)))
+    }
+
+    // returns (series, block) of all blocks held in memory that the operation wrote into
+    pub(crate) fn blocks_of_operation(&self, op: &str) -> Vec<(SeriesId, BlockNumber)> {
+        let mut blocks = vec![];
+        for hd in self.data.iter() {
+            for (block, ops) in &hd.operations {
+                if ops.contains(op) {
+                    blocks.push((*hd.key(), *block));
+                }
+            }
+        }
+        blocks
+    }
+
+    // marks a flushing block as in flight and returns a copy of it, together with the operations
+    // that wrote into it. the block stays in the HotSet until `finish_flush` is called.
+    pub(crate) fn begin_flush(
         &self,
         series: SeriesId,
         block: BlockNumber,
-    ) -> Option<(TxId, SizedBlock)> {
+    ) -> Option<(TxId, SizedBlock, Vec<String>)> {
         match self.data.try_get_mut(&series) {
             dashmap::try_result::TryResult::Present(mut hd) => {
-                hd.value_mut().take_flushing_block(block)
+                let hd = hd.value_mut();
+                if hd.in_flight.contains(&block) {
+                    return None;
+                }
+                let (tx, b) = hd.flushing.get(&block)?;
+                let res = (*tx, b.clone(), hd.block_operations(block));
+                hd.in_flight.insert(block);
+                Some(res)
             }
             dashmap::try_result::TryResult::Absent => None,
             dashmap::try_result::TryResult::Locked => None,
         }
     }
 
+    // drops the block from the HotSet, if it was persisted. otherwise it will be picked up again
+    // by the next write to the series.
+    pub(crate) fn finish_flush(&self, series: SeriesId, block: BlockNumber, persisted: bool) {
+        if let Some(mut hd) = self.data.get_mut(&series) {
+            hd.in_flight.remove(&block);
+            if persisted {
+                hd.take_flushing_block(block);
+            }
+        }
+    }
+
+    // marks the samples in `slots` as deleted, returns how many were marked
+    pub(crate) fn delete<T: BlockWritable>(
+        &self,
+        series: SeriesId,
+        block: BlockNumber,
+        slots: Range<usize>,
+        tx: TxId,
+        op: Option<&str>,
+    ) -> ModifyResult<u64> {
+        self.modify(series, block, tx, op, |b| b.mark_deleted::<T>(slots))
+    }
+
+    // writes the batch into the block if it is held in memory. the batch is expected to use
+    // a last wins collision policy.
+    pub(crate) fn overwrite<T: BlockWritable>(
+        &self,
+        batch: &WriteBatch<T>,
+    ) -> ModifyResult<WriteStats> {
+        self.modify(batch.series.id, batch.block_id, batch.tx, batch.op, |b| {
+            b.write(batch)
+        })
+    }
+
+    fn modify<R>(
+        &self,
+        series: SeriesId,
+        block: BlockNumber,
+        tx: TxId,
+        op: Option<&str>,
+        f: impl FnOnce(&mut SizedBlock) -> R,
+    ) -> ModifyResult<R> {
+        // waits for concurrent writes to the series, they only hold the entry briefly
+        match self.data.get_mut(&series) {
+            Some(mut hd) => hd.value_mut().modify_block(block, tx, op, f),
+            None => ModifyResult::NotHot,
+        }
+    }
+
     pub(crate) fn write<T: BlockWritable>(&self, batch: &WriteBatch<T>) -> WriteResult {
         match self.data.try_get_mut(&batch.series.id) {
             dashmap::try_result::TryResult::Present(mut hd) => {
@@ -1,32 +1,154 @@
 use crate::{
     AppState,
-    api::ApiError,
+    api::{ApiError, as_internal_err},
+    meta::batch::Reservation,
+    operation::{WithOperation, new_operation, with_operation},
     persistence::{self, write_cold},
     wal::next_txid,
 };
-use axum::{Json, extract::State};
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{FromRequest, Request, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{error, info, warn};
 use vodnik_core::{
-    api::{BatchIngest, IngestError, ValueVec},
-    meta::{BlockNumber, BlockWritable, Quality, SeriesId, SeriesMeta, StorableNum, WriteBatch},
+    api::{
+        BATCH_ID_HEADER, BINARY_BATCH_CONTENT_TYPE, BatchIngest, BatchResult, IngestError,
+        MultiBatchIngest, MultiBatchResult, ValueVec, apply_ingest_policy,
+    },
+    helpers,
+    meta::{
+        BlockNumber, BlockWritable, Quality, SeriesId, SeriesMeta, StorableNum, WriteBatch,
+        WriteStats,
+    },
     wal::{TxId, WalEntry, from_write_batch},
 };
 
+pub(crate) mod influx;
+pub(crate) mod stream;
+
 impl From<IngestError> for ApiError {
     fn from(err: IngestError) -> Self {
         match err {
             IngestError::LengthMismatch => ApiError::BadRequest(err.to_string()),
             IngestError::InvalidTimestamp(_) => ApiError::Unprocessable(err.to_string()),
             IngestError::TypeMismatch => ApiError::BadRequest(err.to_string()),
+            IngestError::InvalidBinary(_) => ApiError::BadRequest(err.to_string()),
+            IngestError::Rejected(rejections) => ApiError::Rejected(rejections),
+        }
+    }
+}
+
+// a `BatchIngest` sent either as JSON or in the binary encoding, picked by content type
+pub(crate) struct Batch(BatchIngest);
+
+impl<S: Send + Sync> FromRequest<S> for Batch {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let header_id = req
+            .headers()
+            .get(BATCH_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let binary = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with(BINARY_BATCH_CONTENT_TYPE));
+
+        let mut batch = if binary {
+            let bytes = Bytes::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            BatchIngest::from_binary(&bytes).map_err(|e| ApiError::from(e).into_response())?
+        } else {
+            let Json(batch) = Json::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            batch
+        };
+
+        // an id in the body wins
+        if batch.batch_id.is_none() {
+            batch.batch_id = header_id;
         }
+        Ok(Batch(batch))
     }
 }
 
 pub(crate) async fn batch_ingest(
     State(state): State<AppState>,
-    Json(req): Json<BatchIngest>,
-) -> Result<(), ApiError> {
-    // TODO: limit req size + add streaming endpoint
+    Batch(req): Batch,
+) -> Result<WithOperation<Json<WriteStats>>, ApiError> {
+    // TODO: limit req size, large backfills should use /batch/stream
+    let series = prepare_batch(&state, &req).await?;
+    let op = new_operation();
+    let written = write_batch(&state, &series, req, Some(&op)).await?;
+    Ok(with_operation(written.op, Json(written.stats)))
+}
+
+// validates all batches before anything is written. a failing batch doesn't affect the others.
+pub(crate) async fn multi_batch_ingest(
+    State(state): State<AppState>,
+    Json(req): Json<MultiBatchIngest>,
+) -> Result<WithOperation<Json<MultiBatchResult>>, ApiError> {
+    let mut prepared = Vec::with_capacity(req.batches.len());
+    for batch in &req.batches {
+        prepared.push(prepare_batch(&state, batch).await);
+    }
+
+    // all batches of the request are one operation
+    let op = new_operation();
+    let mut results = Vec::with_capacity(req.batches.len());
+    for (batch, series) in req.batches.into_iter().zip(prepared) {
+        let id = batch.series;
+        let res = match series {
+            Ok(series) => write_batch(&state, &series, batch, Some(&op)).await,
+            Err(e) => Err(e),
+        };
+
+        results.push(match res {
+            Ok(BatchWritten { stats, .. }) => BatchResult {
+                series: id,
+                status: StatusCode::OK.as_u16(),
+                samples: stats.samples as usize,
+                merged: stats.merged as usize,
+                rejected: stats.rejected as usize,
+                error: None,
+                rejections: vec![],
+            },
+            Err(e) => BatchResult {
+                series: id,
+                status: e.status().as_u16(),
+                samples: 0,
+                merged: 0,
+                rejected: 0,
+                error: Some(e.to_string()),
+                rejections: match e {
+                    ApiError::Rejected(rejections) => rejections,
+                    _ => vec![],
+                },
+            },
+        });
+    }
+
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    Ok(with_operation(
+        op,
+        Json(MultiBatchResult {
+            ok: results.len() - failed,
+            failed,
+            results,
+        }),
+    ))
+}
+
+async fn prepare_batch(state: &AppState, req: &BatchIngest) -> Result<SeriesMeta, ApiError> {
     req.validate()?;
     let series = state
         .meta_store
@@ -35,24 +157,89 @@ pub(crate) async fn batch_ingest(
         .map_err(crate::meta::into_api_error)?;
 
     req.check_type(series.storage_type)?;
-    match req.vals {
-        ValueVec::F32(items) => batch_writes(&state, &series, req.ts, items, req.qs).await,
-        ValueVec::F64(items) => batch_writes(&state, &series, req.ts, items, req.qs).await,
-        ValueVec::I32(items) => batch_writes(&state, &series, req.ts, items, req.qs).await,
-        ValueVec::I64(items) => batch_writes(&state, &series, req.ts, items, req.qs).await,
-        ValueVec::U32(items) => batch_writes(&state, &series, req.ts, items, req.qs).await,
-        ValueVec::U64(items) => batch_writes(&state, &series, req.ts, items, req.qs).await,
-        ValueVec::Enum(items) => batch_writes(&state, &series, req.ts, items, req.qs).await,
+    Ok(series)
+}
+
+// outcome of `write_batch`
+pub(crate) struct BatchWritten {
+    pub stats: WriteStats,
+    // operation the samples are recorded under. for a batch written before under the same
+    // batch id that is the operation of the first write.
+    pub op: Option<String>,
+}
+
+pub(crate) async fn write_batch(
+    state: &AppState,
+    series: &SeriesMeta,
+    req: BatchIngest,
+    op: Option<&str>,
+) -> Result<BatchWritten, ApiError> {
+    let batch_id = req.batch_id;
+    if let Some(id) = &batch_id {
+        let reservation = state
+            .batch_ids
+            .reserve(series.id, id, op, now_ms())
+            .await
+            .map_err(as_internal_err)?;
+        match reservation {
+            Reservation::Reserved => {}
+            Reservation::Written(stats, op) => {
+                info!("batch '{id}' of series {} already written", series.id);
+                return Ok(BatchWritten { stats, op });
+            }
+            Reservation::Pending => {
+                return Err(ApiError::Conflict(format!(
+                    "batch '{id}' of series {} is being written, retry later",
+                    series.id
+                )));
+            }
+        }
     }
+
+    let res = match req.vals {
+        ValueVec::F32(items) => batch_writes(state, series, req.ts, items, req.qs, op).await,
+        ValueVec::F64(items) => batch_writes(state, series, req.ts, items, req.qs, op).await,
+        ValueVec::I32(items) => batch_writes(state, series, req.ts, items, req.qs, op).await,
+        ValueVec::I64(items) => batch_writes(state, series, req.ts, items, req.qs, op).await,
+        ValueVec::U32(items) => batch_writes(state, series, req.ts, items, req.qs, op).await,
+        ValueVec::U64(items) => batch_writes(state, series, req.ts, items, req.qs, op).await,
+        ValueVec::Enum(items) => batch_writes(state, series, req.ts, items, req.qs, op).await,
+    };
+
+    if let Some(id) = &batch_id {
+        let done = match &res {
+            Ok(stats) => state.batch_ids.complete(series.id, id, stats).await,
+            Err(_) => state.batch_ids.release(series.id, id).await,
+        };
+        // if this fails the id stays pending, retries get 409 until it expires or a restart
+        _ = done.inspect_err(|e| error!("updating batch '{id}' failed: {e}"));
+    }
+
+    res.map(|stats| BatchWritten {
+        stats,
+        op: op.map(str::to_string),
+    })
 }
 
 async fn batch_writes<T: BlockWritable>(
     state: &AppState,
     series: &SeriesMeta,
     ts: Vec<u64>,
-    vals: Vec<T>,
-    qs: Vec<Quality>,
-) -> Result<(), ApiError> {
+    mut vals: Vec<T>,
+    mut qs: Vec<Quality>,
+    op: Option<&str>,
+) -> Result<WriteStats, ApiError> {
+    apply_ingest_policy(series, now_ms(), &ts, &mut vals, &mut qs)?;
+
+    let (ts, vals, qs, rejected) = align_samples(series, ts, vals, qs);
+    let mut stats = WriteStats {
+        rejected,
+        ..Default::default()
+    };
+    if ts.is_empty() {
+        return Ok(stats);
+    }
+
     let mut start_index = 0;
     let mut current_block = vodnik_core::helpers::get_block_id(&series, ts[0]) as usize;
 
@@ -67,9 +254,10 @@ async fn batch_writes<T: BlockWritable>(
                 &vals[start_index..i],
                 &qs[start_index..i],
                 TxId(next_txid()),
-            );
+            )
+            .with_op(op);
 
-            write_chunk(&state, &batch, false).await?;
+            stats += write_chunk(&state, &batch, false).await?;
 
             start_index = i;
             current_block = next_block;
@@ -83,9 +271,47 @@ async fn batch_writes<T: BlockWritable>(
         &vals[start_index..ts.len()],
         &qs[start_index..ts.len()],
         TxId(next_txid()),
+    )
+    .with_op(op);
+
+    stats += write_chunk(&state, &batch, false).await?;
+    Ok(stats)
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// maps the timestamps onto the grid of the series, samples rejected by its alignment policy are
+// dropped and counted
+fn align_samples<T>(
+    series: &SeriesMeta,
+    ts: Vec<u64>,
+    vals: Vec<T>,
+    qs: Vec<Quality>,
+) -> (Vec<u64>, Vec<T>, Vec<Quality>, u64) {
+    let len = ts.len();
+    let (mut aligned_ts, mut aligned_vals, mut aligned_qs) = (
+        Vec::with_capacity(len),
+        Vec::with_capacity(len),
+        Vec::with_capacity(len),
     );
+    let mut rejected = 0;
+
+    for ((t, v), q) in ts.into_iter().zip(vals).zip(qs) {
+        match helpers::align_to_grid(series, t) {
+            Some(t) => {
+                aligned_ts.push(t);
+                aligned_vals.push(v);
+                aligned_qs.push(q);
+            }
+            None => rejected += 1,
+        }
+    }
 
-    write_chunk(&state, &batch, false).await
+    (aligned_ts, aligned_vals, aligned_qs, rejected)
 }
 
 fn write_batch_to_val<T: StorableNum>(
@@ -101,7 +327,7 @@ fn write_batch_to_val<T: StorableNum>(
     Ok(())
 }
 
-fn write_flush_to_wal<T: StorableNum>(
+pub(crate) fn write_flush_to_wal<T: StorableNum>(
     state: &AppState,
     tx: TxId,
     series: SeriesId,
@@ -120,7 +346,7 @@ pub(crate) async fn write_chunk<'a, T: BlockWritable>(
     state: &AppState,
     batch: &'a WriteBatch<'a, T>,
     replay: bool,
-) -> Result<(), ApiError> {
+) -> Result<WriteStats, ApiError> {
     const MAX_RETRIES: u32 = 3; // TODO: settings!
     let mut attempt = 0;
     if !replay {
@@ -131,7 +357,9 @@ pub(crate) async fn write_chunk<'a, T: BlockWritable>(
         let res = state.hot.write(batch);
 
         match res {
-            crate::hot::WriteResult::Ok { flushing, .. } => {
+            crate::hot::WriteResult::Ok {
+                flushing, stats, ..
+            } => {
                 if !flushing.is_empty() {
                     let s = state.clone();
                     let sid = batch.series.id;
@@ -140,7 +368,7 @@ pub(crate) async fn write_chunk<'a, T: BlockWritable>(
                         flush_background(&s, sid, flushing).await;
                     });
                 }
-                return Ok(());
+                return Ok(stats);
             }
             crate::hot::WriteResult::Busy => {
                 attempt += 1;
@@ -151,7 +379,8 @@ pub(crate) async fn write_chunk<'a, T: BlockWritable>(
                 tokio::task::yield_now().await;
             }
             crate::hot::WriteResult::NeedsColdStore => {
-                let cold_write_result = write_cold(&state.storage, &state.block_meta, batch).await;
+                let cold_write_result =
+                    write_cold(&state.storage, &state.block_meta, &state.block_locks, batch).await;
                 if cold_write_result.is_ok() && !replay {
                     // TODO: in case this fails, we prob want to do more granular err handling later
                     //       for example in case the disk is full, we might go into a state, where we reject all new data alltogther
@@ -167,20 +396,25 @@ pub(crate) async fn write_chunk<'a, T: BlockWritable>(
 
 async fn flush_background(state: &AppState, series: SeriesId, blocks_to_flush: Vec<BlockNumber>) {
     for block_id in blocks_to_flush.iter() {
-        if let Some((tx, block)) = state.hot.take_flushing_block(series, *block_id) {
+        // deletes and overwrites of the block wait until it is persisted
+        let _guard = state.block_locks.lock(series, *block_id).await;
+        if let Some((tx, block, ops)) = state.hot.begin_flush(series, *block_id) {
+            let ops: Vec<&str> = ops.iter().map(String::as_str).collect();
             let r = persistence::flush_block(
                 &state.storage,
                 &state.block_meta,
                 series,
                 *block_id,
                 &block,
+                tx,
+                &ops,
             )
             .await;
             if r.is_ok() {
-                // TODO: in case flushing fails, we want to requeue that block
                 _ = write_flush_to_wal::<u8>(state, tx, series, *block_id);
                 info!("flushed block {block_id:?} for series {series}");
             }
+            state.hot.finish_flush(series, *block_id, r.is_ok());
         }
     }
 }
@@ -0,0 +1,396 @@
+use std::{
+    collections::{BTreeMap, HashMap, btree_map::Entry},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use vodnik_core::{
+    api::{BatchIngest, ValueVec},
+    meta::{Quality, SeriesId, SeriesMeta},
+};
+
+use crate::{
+    AppState,
+    api::ApiError,
+    ingest::write_batch,
+    meta::{
+        into_api_error,
+        label::{LabelMatcher, LabelOp, MatchMode},
+    },
+    operation::{WithOperation, new_operation, with_operation},
+};
+
+// field holding the quality of all other fields of a line
+const QUALITY_FIELD: &str = "quality";
+// field that maps to the measurement itself, instead of `measurement_field`
+const VALUE_FIELD: &str = "value";
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub enum Precision {
+    #[default]
+    #[serde(rename = "ns", alias = "n")]
+    Nanos,
+    #[serde(rename = "us", alias = "u")]
+    Micros,
+    #[serde(rename = "ms")]
+    Millis,
+    #[serde(rename = "s")]
+    Seconds,
+}
+
+impl Precision {
+    fn to_ms(self, ts: u64) -> u64 {
+        match self {
+            Precision::Nanos => ts / 1_000_000,
+            Precision::Micros => ts / 1_000,
+            Precision::Millis => ts,
+            Precision::Seconds => ts.saturating_mul(1000),
+        }
+    }
+}
+
+// how a line protocol point is mapped to a series
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeriesMapping {
+    // series named `measurement_field`, or `measurement` for the field `value`
+    #[default]
+    Name,
+    // the single series with the labels measurement=<measurement>, field=<field> (not needed for
+    // the field `value`) and all tags of the point
+    Labels,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InfluxQuery {
+    #[serde(default)]
+    pub precision: Precision,
+    #[serde(default)]
+    pub map: SeriesMapping,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldValue {
+    Float(f64),
+    Int(i64),
+    UInt(u64),
+    Bool(bool),
+}
+
+// target series and its (ts, value, quality) samples
+type SeriesSamples = (SeriesMeta, Vec<(u64, FieldValue, Quality)>);
+
+#[derive(Debug)]
+struct Point {
+    measurement: String,
+    tags: Vec<(String, String)>,
+    fields: Vec<(String, FieldValue)>,
+    ts: Option<u64>,
+}
+
+// POST /write and /api/v2/write, InfluxDB line protocol:
+// measurement[,tag=value...] field=value[,field=value...] [timestamp]
+//
+// all lines are parsed and mapped to series before anything is written.
+pub(crate) async fn influx_write(
+    State(state): State<AppState>,
+    Query(query): Query<InfluxQuery>,
+    body: String,
+) -> Result<WithOperation<StatusCode>, ApiError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+
+    let mut resolved: HashMap<String, SeriesMeta> = HashMap::new();
+    let mut samples: BTreeMap<SeriesId, SeriesSamples> = BTreeMap::new();
+
+    for (n, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_err = |e: String| ApiError::BadRequest(format!("line {}: {e}", n + 1));
+
+        let point = parse_line(line).map_err(line_err)?;
+        let ts = point.ts.map_or(now, |ts| query.precision.to_ms(ts));
+        let q = point.quality().map_err(line_err)?;
+
+        for (field, value) in &point.fields {
+            if field == QUALITY_FIELD {
+                continue;
+            }
+
+            let key = point.series_key(field);
+            let series = match resolved.get(&key) {
+                Some(series) => series.clone(),
+                None => {
+                    let series = resolve_series(&state, query.map, &point, field)
+                        .await
+                        .map_err(|e| match e {
+                            ApiError::BadRequest(msg) => line_err(msg),
+                            e => e,
+                        })?;
+                    resolved.insert(key, series.clone());
+                    series
+                }
+            };
+
+            match samples.entry(series.id) {
+                Entry::Occupied(mut e) => e.get_mut().1.push((ts, *value, q)),
+                Entry::Vacant(e) => {
+                    e.insert((series, vec![(ts, *value, q)]));
+                }
+            }
+        }
+    }
+
+    let mut batches = Vec::with_capacity(samples.len());
+    for (id, (series, mut points)) in samples {
+        points.sort_by_key(|(ts, _, _)| *ts);
+
+        let mut vals = ValueVec::with_type(series.storage_type);
+        for (_, v, _) in &points {
+            push_value(&mut vals, *v).map_err(|e| {
+                ApiError::BadRequest(format!("series '{}' ({id}): {e}", series.name))
+            })?;
+        }
+
+        let batch = BatchIngest {
+            series: id,
+            ts: points.iter().map(|(ts, _, _)| *ts).collect(),
+            qs: points.iter().map(|(_, _, q)| *q).collect(),
+            batch_id: None,
+            vals,
+        };
+        batches.push((series, batch));
+    }
+
+    let op = new_operation();
+    for (series, batch) in batches {
+        write_batch(&state, &series, batch, Some(&op)).await?;
+    }
+
+    Ok(with_operation(op, StatusCode::NO_CONTENT))
+}
+
+async fn resolve_series(
+    state: &AppState,
+    mapping: SeriesMapping,
+    point: &Point,
+    field: &str,
+) -> Result<SeriesMeta, ApiError> {
+    let (what, mut found) = match mapping {
+        SeriesMapping::Name => {
+            let name = if field == VALUE_FIELD {
+                point.measurement.clone()
+            } else {
+                format!("{}_{field}", point.measurement)
+            };
+            let found = state
+                .meta_store
+                .find_by_name(&name)
+                .await
+                .map_err(into_api_error)?;
+            (format!("series named '{name}'"), found)
+        }
+        SeriesMapping::Labels => {
+            let eq = |name: &str, value: &str| LabelMatcher {
+                name: name.to_string(),
+                op: LabelOp::Eq,
+                value: value.to_string(),
+            };
+            let mut matchers = vec![eq("measurement", &point.measurement)];
+            if field != VALUE_FIELD {
+                matchers.push(eq("field", field));
+            }
+            matchers.extend(point.tags.iter().map(|(k, v)| eq(k, v)));
+
+            let found = state
+                .meta_store
+                .find(&matchers, MatchMode::All)
+                .await
+                .map_err(into_api_error)?;
+            (format!("series for {}", point.series_key(field)), found)
+        }
+    };
+
+    match found.len() {
+        0 => Err(ApiError::NotFound(format!("no {what}"))),
+        1 => Ok(found.remove(0)),
+        n => Err(ApiError::BadRequest(format!("{n} {what}, expected one"))),
+    }
+}
+
+impl Point {
+    // measurement,tags field, identifies the target series of a field within a request
+    fn series_key(&self, field: &str) -> String {
+        let mut key = self.measurement.clone();
+        for (k, v) in &self.tags {
+            key.push_str(&format!(",{k}={v}"));
+        }
+        key.push_str(&format!(" {field}"));
+        key
+    }
+
+    fn quality(&self) -> Result<Quality, String> {
+        let Some((_, v)) = self.fields.iter().find(|(k, _)| k == QUALITY_FIELD) else {
+            return Ok(Quality::GOOD);
+        };
+        let q = match *v {
+            FieldValue::Int(i) => u8::try_from(i).ok(),
+            FieldValue::UInt(u) => u8::try_from(u).ok(),
+            FieldValue::Float(f) if f.fract() == 0.0 && (0.0..=255.0).contains(&f) => Some(f as u8),
+            _ => None,
+        };
+        q.map(Quality)
+            .ok_or_else(|| format!("invalid quality {v:?}, expected 0..=255"))
+    }
+}
+
+fn parse_line(line: &str) -> Result<Point, String> {
+    let sections: Vec<&str> = split_raw(line, ' ')
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .collect();
+    if !(2..=3).contains(&sections.len()) {
+        return Err("expected 'measurement[,tags] fields [timestamp]'".to_string());
+    }
+
+    let mut key = split_raw(sections[0], ',').into_iter();
+    let measurement = unescape(key.next().unwrap_or_default());
+    if measurement.is_empty() {
+        return Err("missing measurement".to_string());
+    }
+
+    let mut tags = vec![];
+    for tag in key {
+        let (k, v) = split_pair(tag).ok_or_else(|| format!("invalid tag '{tag}'"))?;
+        tags.push((unescape(k), unescape(v)));
+    }
+    tags.sort();
+
+    let mut fields = vec![];
+    for field in split_raw(sections[1], ',') {
+        let (k, v) = split_pair(field).ok_or_else(|| format!("invalid field '{field}'"))?;
+        fields.push((unescape(k), parse_field(v)?));
+    }
+
+    let ts = match sections.get(2) {
+        Some(ts) => Some(
+            ts.parse::<u64>()
+                .map_err(|_| format!("invalid timestamp '{ts}'"))?,
+        ),
+        None => None,
+    };
+
+    Ok(Point {
+        measurement,
+        tags,
+        fields,
+        ts,
+    })
+}
+
+fn parse_field(v: &str) -> Result<FieldValue, String> {
+    let invalid = || format!("invalid field value '{v}'");
+    if v.starts_with('"') {
+        return Err(format!("string field value {v} is not supported"));
+    }
+
+    if let Some(i) = v.strip_suffix('i') {
+        return i.parse().map(FieldValue::Int).map_err(|_| invalid());
+    }
+    if let Some(u) = v.strip_suffix('u') {
+        return u.parse().map(FieldValue::UInt).map_err(|_| invalid());
+    }
+    match v {
+        "t" | "T" | "true" | "True" | "TRUE" => Ok(FieldValue::Bool(true)),
+        "f" | "F" | "false" | "False" | "FALSE" => Ok(FieldValue::Bool(false)),
+        _ => v.parse().map(FieldValue::Float).map_err(|_| invalid()),
+    }
+}
+
+// splits at `sep`, skipping escaped chars and quoted strings. the parts are still escaped.
+fn split_raw(s: &str, sep: char) -> Vec<&str> {
+    let (mut parts, mut start) = (vec![], 0);
+    let (mut escaped, mut quoted) = (false, false);
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn split_pair(s: &str) -> Option<(&str, &str)> {
+    match split_raw(s, '=').as_slice() {
+        [k, v] if !k.is_empty() && !v.is_empty() => Some((k, v)),
+        _ => None,
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\'
+            && let Some(&next) = chars.peek()
+            && matches!(next, ',' | ' ' | '=' | '"' | '\\')
+        {
+            out.push(next);
+            chars.next();
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn push_value(vals: &mut ValueVec, v: FieldValue) -> Result<(), String> {
+    fn int<T: TryFrom<i64> + TryFrom<u64>>(v: FieldValue) -> Result<T, String> {
+        let res = match v {
+            FieldValue::Int(i) => T::try_from(i).ok(),
+            FieldValue::UInt(u) => T::try_from(u).ok(),
+            FieldValue::Bool(b) => T::try_from(b as u64).ok(),
+            // whole numbers sent as floats, e.g. by collectors without integer support
+            FieldValue::Float(f) if f.fract() == 0.0 && f.abs() < 2f64.powi(53) => {
+                T::try_from(f as i64).ok()
+            }
+            FieldValue::Float(_) => None,
+        };
+        res.ok_or_else(|| format!("value {v:?} doesn't fit the series type"))
+    }
+    let float = |v: FieldValue| match v {
+        FieldValue::Float(f) => f,
+        FieldValue::Int(i) => i as f64,
+        FieldValue::UInt(u) => u as f64,
+        FieldValue::Bool(b) => b as u8 as f64,
+    };
+
+    match vals {
+        ValueVec::F32(vec) => vec.push(float(v) as f32),
+        ValueVec::F64(vec) => vec.push(float(v)),
+        ValueVec::I32(vec) => vec.push(int(v)?),
+        ValueVec::I64(vec) => vec.push(int(v)?),
+        ValueVec::U32(vec) => vec.push(int(v)?),
+        ValueVec::U64(vec) => vec.push(int(v)?),
+        ValueVec::Enum(vec) => vec.push(int(v)?),
+    }
+    Ok(())
+}
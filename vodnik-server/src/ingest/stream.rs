@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, btree_map::Entry};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use bytes::{Buf, BytesMut};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::Number;
+use tracing::info;
+use vodnik_core::{
+    api::{
+        RawTimestamp, SeriesIngestCount, StreamIngestError, StreamIngestResult, TimestampFormat,
+        ValueVec, check_sample,
+    },
+    helpers,
+    meta::{Quality, SeriesId, SeriesMeta, WriteStats},
+};
+
+use crate::{
+    AppState,
+    api::ApiError,
+    ingest::{batch_writes, now_ms},
+    meta::into_api_error,
+    operation::{WithOperation, new_operation, with_operation},
+};
+
+// a single NDJSON line is never buffered beyond this
+const MAX_LINE_BYTES: usize = 64 * 1024;
+// samples buffered per series before they are written, even if the block isn't complete
+const MAX_PENDING_SAMPLES: usize = 64 * 1024;
+// series with buffered samples, all buffers are written before another series is added
+const MAX_PENDING_SERIES: usize = 1024;
+
+// one sample per line:
+// {"series": 14, "ts": 1767111429344, "value": 12.5, "q": 192}
+#[derive(Debug, Deserialize)]
+struct StreamSample {
+    series: SeriesId,
+    // interpreted with the `TimestampFormat` of the query
+    ts: RawTimestamp,
+    value: Number,
+    #[serde(default)]
+    q: Quality,
+}
+
+// error response, with the samples written before the error and the operation they belong to
+type StreamError = WithOperation<(StatusCode, Json<StreamIngestError>)>;
+
+// samples of a single series and block, not yet written
+struct Pending {
+    series: SeriesMeta,
+    block: u64,
+    last_ts: Option<u64>,
+    ts: Vec<u64>,
+    vals: ValueVec,
+    qs: Vec<Quality>,
+    written: WriteStats,
+}
+
+impl Pending {
+    fn new(series: SeriesMeta) -> Self {
+        Self {
+            vals: ValueVec::with_type(series.storage_type),
+            series,
+            block: 0,
+            last_ts: None,
+            ts: vec![],
+            qs: vec![],
+            written: WriteStats::default(),
+        }
+    }
+
+    fn push(&mut self, ts: u64, sample: &StreamSample) -> Result<(), String> {
+        if self.last_ts.is_some_and(|last| ts < last) {
+            return Err(format!(
+                "timestamps of series {} must be sorted in ascending order",
+                self.series.id
+            ));
+        }
+        push_value(&mut self.vals, &sample.value)?;
+        self.ts.push(ts);
+        self.qs.push(sample.q);
+        self.last_ts = Some(ts);
+        Ok(())
+    }
+
+    async fn write(&mut self, state: &AppState, op: &str) -> Result<(), ApiError> {
+        if self.ts.is_empty() {
+            return Ok(());
+        }
+
+        let ts = std::mem::take(&mut self.ts);
+        let qs = std::mem::take(&mut self.qs);
+        let vals = std::mem::replace(
+            &mut self.vals,
+            ValueVec::with_type(self.series.storage_type),
+        );
+        let series = &self.series;
+        self.written += match vals {
+            ValueVec::F32(items) => batch_writes(state, series, ts, items, qs, Some(op)).await,
+            ValueVec::F64(items) => batch_writes(state, series, ts, items, qs, Some(op)).await,
+            ValueVec::I32(items) => batch_writes(state, series, ts, items, qs, Some(op)).await,
+            ValueVec::I64(items) => batch_writes(state, series, ts, items, qs, Some(op)).await,
+            ValueVec::U32(items) => batch_writes(state, series, ts, items, qs, Some(op)).await,
+            ValueVec::U64(items) => batch_writes(state, series, ts, items, qs, Some(op)).await,
+            ValueVec::Enum(items) => batch_writes(state, series, ts, items, qs, Some(op)).await,
+        }?;
+        Ok(())
+    }
+}
+
+pub(crate) fn push_value(vals: &mut ValueVec, n: &Number) -> Result<(), String> {
+    fn int<T: TryFrom<i64> + TryFrom<u64>>(n: &Number) -> Result<T, String> {
+        let v = match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => T::try_from(i).ok(),
+            (_, Some(u)) => T::try_from(u).ok(),
+            _ => None,
+        };
+        v.ok_or_else(|| format!("value {n} doesn't fit the series type"))
+    }
+    let float = |n: &Number| n.as_f64().ok_or_else(|| format!("invalid value {n}"));
+
+    match vals {
+        ValueVec::F32(v) => v.push(float(n)? as f32),
+        ValueVec::F64(v) => v.push(float(n)?),
+        ValueVec::I32(v) => v.push(int(n)?),
+        ValueVec::I64(v) => v.push(int(n)?),
+        ValueVec::U32(v) => v.push(int(n)?),
+        ValueVec::U64(v) => v.push(int(n)?),
+        ValueVec::Enum(v) => v.push(int(n)?),
+    }
+    Ok(())
+}
+
+struct StreamIngest<'a> {
+    state: &'a AppState,
+    format: TimestampFormat,
+    // reference of the future timestamp check, taken once per request
+    now_ms: u64,
+    series: BTreeMap<SeriesId, Pending>,
+    lines: u64,
+    op: String,
+}
+
+impl StreamIngest<'_> {
+    async fn line(&mut self, line: &[u8]) -> Result<(), ApiError> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        self.lines += 1;
+
+        let sample: StreamSample = serde_json::from_slice(line)
+            .map_err(|e| ApiError::BadRequest(format!("line {}: {e}", self.lines)))?;
+        let ts = self
+            .format
+            .to_ms(&sample.ts)
+            .map_err(|e| ApiError::Unprocessable(format!("line {}: {e}", self.lines)))?;
+
+        if !self.series.contains_key(&sample.series) {
+            let buffered = self.series.values().filter(|p| !p.ts.is_empty()).count();
+            if buffered >= MAX_PENDING_SERIES {
+                self.write_all().await?;
+            }
+        }
+
+        let pending = match self.series.entry(sample.series) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let series = self
+                    .state
+                    .meta_store
+                    .get(sample.series)
+                    .await
+                    .map_err(into_api_error)?;
+                e.insert(Pending::new(series))
+            }
+        };
+
+        check_sample(&pending.series, self.now_ms, ts, sample.q)
+            .map_err(|reason| ApiError::Unprocessable(format!("line {}: {reason}", self.lines)))?;
+
+        // a batch never spans more than one block
+        let block = helpers::get_block_id(&pending.series, ts);
+        if block != pending.block || pending.ts.len() >= MAX_PENDING_SAMPLES {
+            pending.write(self.state, &self.op).await?;
+            pending.block = block;
+        }
+        pending
+            .push(ts, &sample)
+            .map_err(|e| ApiError::BadRequest(format!("line {}: {e}", self.lines)))
+    }
+
+    async fn write_all(&mut self) -> Result<(), ApiError> {
+        for pending in self.series.values_mut() {
+            pending.write(self.state, &self.op).await?;
+        }
+        Ok(())
+    }
+
+    fn result(&self) -> StreamIngestResult {
+        StreamIngestResult {
+            lines: self.lines,
+            samples: self.series.values().map(|p| p.written.samples).sum(),
+            merged: self.series.values().map(|p| p.written.merged).sum(),
+            rejected: self.series.values().map(|p| p.written.rejected).sum(),
+            series: self
+                .series
+                .iter()
+                .map(|(id, p)| SeriesIngestCount {
+                    series: *id,
+                    accepted: p.written.samples,
+                    merged: p.written.merged,
+                    rejected: p.written.rejected,
+                })
+                .collect(),
+        }
+    }
+}
+
+// POST /batch/stream, NDJSON body with one sample per line. samples are split into per block
+// batches while the body is read, memory is bounded by the pending buffers instead of the body
+// size. samples of a series must be sorted by ts, series may be interleaved.
+//
+// on an invalid line the samples of all previous lines are still written. the error names the
+// line and counts the written samples, the operation header allows to revert them.
+// ts_unit, ts_zone and ts_dst query parameters apply to the timestamps of all lines.
+pub(crate) async fn stream_ingest(
+    State(state): State<AppState>,
+    Query(format): Query<TimestampFormat>,
+    body: Body,
+) -> Result<WithOperation<Json<StreamIngestResult>>, StreamError> {
+    let mut ingest = StreamIngest {
+        state: &state,
+        format,
+        now_ms: now_ms(),
+        series: BTreeMap::new(),
+        lines: 0,
+        op: new_operation(),
+    };
+
+    let res = read_lines(&mut ingest, body).await;
+    let written = ingest.write_all().await;
+
+    let result = ingest.result();
+    if let Err(e) = res.and(written) {
+        info!(
+            "stream ingest failed after {} samples of {} series: {e}",
+            result.samples,
+            result.series.len()
+        );
+        return Err(with_operation(
+            ingest.op,
+            (
+                e.status(),
+                Json(StreamIngestError {
+                    error: e.to_string(),
+                    written: result,
+                }),
+            ),
+        ));
+    }
+
+    info!(
+        "stream ingest: {} samples of {} series",
+        result.samples,
+        result.series.len()
+    );
+    Ok(with_operation(ingest.op, Json(result)))
+}
+
+async fn read_lines(ingest: &mut StreamIngest<'_>, body: Body) -> Result<(), ApiError> {
+    let mut stream = body.into_data_stream();
+    let mut buf = BytesMut::new();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(format!("reading body: {e}")))?;
+        buf.extend_from_slice(&chunk);
+
+        while let Some(end) = buf.iter().position(|b| *b == b'\n') {
+            let line = buf.split_to(end + 1);
+            ingest.line(&line).await?;
+        }
+
+        if buf.len() > MAX_LINE_BYTES {
+            return Err(ApiError::BadRequest(format!(
+                "line {} exceeds {MAX_LINE_BYTES} bytes",
+                ingest.lines + 1
+            )));
+        }
+    }
+
+    // last line without a trailing newline
+    if buf.has_remaining() {
+        ingest.line(&buf).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{body::to_bytes, response::IntoResponse};
+    use opendal::Operator;
+    use sea_orm::ConnectionTrait;
+    use ulid::Ulid;
+    use vodnik_core::{api::OPERATION_HEADER, wal::WalSync};
+
+    use super::*;
+    use crate::{
+        crud::CreateSeries,
+        gc::GcConfig,
+        hot::HotSet,
+        meta::{
+            audit::AuditStore, batch::BatchIdStore, block::BlockMetaStore, store::SqlMetaStore,
+        },
+        persistence::BlockLocks,
+        wal::{Wal, WalConfig},
+    };
+
+    // state on a fresh sqlite file and in memory storage, with one f32 series
+    async fn test_state() -> (AppState, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("vodnik-stream-{}", Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.join("db.sqlite").display());
+        let db = crate::meta::store::create(&url).await.unwrap();
+        db.execute_unprepared(include_str!("../meta/schema.sql"))
+            .await
+            .unwrap();
+
+        let state = AppState {
+            meta_store: SqlMetaStore::new(db.clone()),
+            block_meta: BlockMetaStore::new(db.clone()),
+            batch_ids: BatchIdStore::new(db.clone(), 60_000),
+            audit: AuditStore::new(db),
+            storage: Operator::new(opendal::services::Memory::default())
+                .unwrap()
+                .finish(),
+            block_locks: BlockLocks::default(),
+            hot: Arc::new(HotSet::new()),
+            wal: Arc::new(Mutex::new(
+                Wal::new(WalConfig {
+                    dir: dir.join("wal"),
+                    max_file_size: 1024 * 1024,
+                    sync_mode: WalSync::Immediate,
+                })
+                .unwrap(),
+            )),
+            gc: GcConfig {
+                grace: Duration::from_secs(60),
+                version_retention: None,
+            },
+        };
+
+        let create: CreateSeries = serde_json::from_str(
+            r#"{"name": "t", "storage_type": "Float32", "sample_length": 1,
+                "sample_resolution": "Second", "labels": []}"#,
+        )
+        .unwrap();
+        state
+            .meta_store
+            .create(&SeriesMeta::from(&create))
+            .await
+            .unwrap();
+
+        (state, dir)
+    }
+
+    #[tokio::test]
+    async fn invalid_line_returns_operation_and_written_counts() {
+        let (state, dir) = test_state().await;
+        let body = concat!(
+            r#"{"series": 1, "ts": 1700000000000, "value": 1.5}"#,
+            "\n",
+            r#"{"series": 1, "ts": 1700000001000, "value": 2.5}"#,
+            "\n",
+            r#"{"series": 1, "ts": 1700000002000}"#,
+            "\n",
+        );
+
+        let Err(err) = stream_ingest(
+            State(state.clone()),
+            Query(TimestampFormat::default()),
+            Body::from(body),
+        )
+        .await
+        else {
+            panic!("invalid line accepted");
+        };
+        let res = err.into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let op = res.headers()[OPERATION_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let err: StreamIngestError = serde_json::from_slice(&body).unwrap();
+        assert!(err.error.starts_with("line 3:"), "{}", err.error);
+        assert_eq!((err.written.lines, err.written.samples), (3, 2));
+        assert_eq!(err.written.series[0].accepted, 2);
+
+        // the samples of the first lines are written under the returned operation
+        assert_eq!(state.hot.blocks_of_operation(&op).len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
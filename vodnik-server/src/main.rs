@@ -5,17 +5,20 @@ use std::{
         Arc, Mutex,
         atomic::{AtomicUsize, Ordering},
     },
+    time::Duration,
 };
 
 use axum::{Router, extract::DefaultBodyLimit, routing::get};
 use opendal::Operator;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
-use tracing::{info, level_filters::LevelFilter};
+use tracing::{error, info, level_filters::LevelFilter};
 use tracing_subscriber::{EnvFilter, prelude::*};
 
 use crate::{
+    gc::GcConfig,
     hot::HotSet,
-    meta::{block::BlockMetaStore, store::SqlMetaStore},
+    meta::{audit::AuditStore, batch::BatchIdStore, block::BlockMetaStore, store::SqlMetaStore},
+    persistence::BlockLocks,
     wal::{Wal, WalConfig},
 };
 
@@ -23,9 +26,16 @@ use vodnik_core::{VODNIK_ASCII, VODNIK_ASCII_REV, wal::WalSync};
 
 mod api;
 mod crud;
+mod edit;
+mod gc;
 mod hot;
 mod ingest;
 mod meta;
+#[cfg(feature = "modbus")]
+mod modbus;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod operation;
 mod persistence;
 mod query;
 mod wal;
@@ -34,9 +44,13 @@ mod wal;
 struct AppState {
     pub meta_store: SqlMetaStore,
     pub block_meta: BlockMetaStore,
+    pub batch_ids: BatchIdStore,
+    pub audit: AuditStore,
     pub storage: Operator,
+    pub block_locks: BlockLocks,
     pub hot: Arc<HotSet>,
     pub wal: Arc<Mutex<Wal>>,
+    pub gc: GcConfig,
 }
 
 #[tokio::main]
@@ -59,7 +73,9 @@ async fn main() -> anyhow::Result<()> {
     let db = meta::store::create(&db_url).await?;
 
     let store = SqlMetaStore::new(db.clone());
-    let block_store = BlockMetaStore::new(db);
+    let block_store = BlockMetaStore::new(db.clone());
+    let audit = AuditStore::new(db.clone());
+    let batch_ids = BatchIdStore::new(db, BATCH_ID_TTL.as_millis() as u64);
 
     let mut builder = opendal::services::Fs::default();
     builder = builder.root("/tmp/vodnik_test");
@@ -80,9 +96,21 @@ async fn main() -> anyhow::Result<()> {
     let state = AppState {
         meta_store: store,
         storage: op,
+        block_locks: BlockLocks::default(),
         block_meta: block_store,
+        batch_ids,
+        audit,
         hot: Arc::new(HotSet::new()),
         wal: Arc::new(Mutex::new(Wal::new(wal_config)?)),
+        gc: GcConfig {
+            grace: GC_GRACE,
+            // unset keeps all block versions
+            version_retention: env::var("VODNIK_VERSION_RETENTION_DAYS")
+                .ok()
+                .map(|days| days.parse::<u64>())
+                .transpose()?
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        },
     };
 
     // recovery
@@ -95,6 +123,23 @@ async fn main() -> anyhow::Result<()> {
     wal::cleanup_wal_files(wal_dir)?;
     info!("recovery completed.");
 
+    // writes interrupted by the restart are replayed or lost, their batches may be retried
+    let released = state.batch_ids.release_pending().await?;
+    info!("released {released} pending batch ids");
+
+    spawn_batch_id_purge(state.batch_ids.clone());
+    gc::spawn(state.clone());
+
+    #[cfg(feature = "mqtt")]
+    if let Ok(path) = env::var("VODNIK_MQTT_CONFIG") {
+        mqtt::spawn(mqtt::MqttConfig::load(&path)?, state.clone());
+    }
+
+    #[cfg(feature = "modbus")]
+    if let Ok(path) = env::var("VODNIK_MODBUS_CONFIG") {
+        modbus::spawn(modbus::ModbusConfig::load(&path)?, state.clone());
+    }
+
     let port = 8123;
 
     let app = Router::new()
@@ -114,6 +159,24 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+// TODO: settings!
+const BATCH_ID_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const BATCH_ID_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const GC_GRACE: Duration = Duration::from_secs(60 * 60);
+
+fn spawn_batch_id_purge(batch_ids: BatchIdStore) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(BATCH_ID_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match batch_ids.purge(ingest::now_ms()).await {
+                Ok(n) => info!("purged {n} expired batch ids"),
+                Err(e) => error!("purging batch ids failed: {e}"),
+            }
+        }
+    });
+}
+
 static CNT: AtomicUsize = AtomicUsize::new(0);
 
 async fn health() -> &'static str {
@@ -3,8 +3,13 @@ use vodnik_core::meta::SeriesId;
 
 use crate::api::ApiError;
 
+pub mod audit;
+pub mod batch;
 pub mod block;
+pub mod label;
 pub mod store;
+pub mod version;
+pub mod version_operation;
 
 #[derive(Error, Debug)]
 pub enum MetaStoreError {
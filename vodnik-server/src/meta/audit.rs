@@ -0,0 +1,120 @@
+use sea_orm::ActiveValue::NotSet;
+use sea_orm::entity::prelude::*;
+use sea_orm::{QueryFilter, QueryOrder, QuerySelect, Set};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use vodnik_core::meta::{BlockNumber, SeriesId};
+
+// audit trail of manual overwrites, one record per rewritten block. the replaced block version
+// stays in storage under `previous_object_key`.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "overwrite_audit")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub series_id: i64,
+    pub block_id: i64,
+
+    // overwritten range [from_ts, to_ts) in ms after UNIX EPOCH
+    pub from_ts: i64,
+    pub to_ts: i64,
+    pub samples: i64,
+
+    pub user_name: String,
+    pub reason: String,
+
+    // None if the block wasn't stored before
+    pub previous_object_key: Option<String>,
+    pub object_key: String,
+    // the overwrite request, it can be reverted as a whole
+    pub operation: String,
+
+    // ms after UNIX EPOCH
+    pub created_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct NewAuditRecord<'a> {
+    pub series_id: SeriesId,
+    pub block_id: BlockNumber,
+    pub from: u64,
+    pub to: u64,
+    pub samples: u64,
+    pub user: &'a str,
+    pub reason: &'a str,
+    pub previous_object_key: Option<String>,
+    pub object_key: String,
+    pub operation: &'a str,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditStore {
+    db: DatabaseConnection,
+}
+
+impl AuditStore {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn insert(&self, record: NewAuditRecord<'_>, now_ms: u64) -> Result<(), DbErr> {
+        let model = ActiveModel {
+            id: NotSet,
+            series_id: Set(record.series_id.0.get() as i64),
+            block_id: Set(record.block_id.0 as i64),
+            from_ts: Set(record.from as i64),
+            to_ts: Set(record.to as i64),
+            samples: Set(record.samples as i64),
+            user_name: Set(record.user.to_string()),
+            reason: Set(record.reason.to_string()),
+            previous_object_key: Set(record.previous_object_key),
+            object_key: Set(record.object_key),
+            operation: Set(record.operation.to_string()),
+            created_at: Set(now_ms as i64),
+        };
+
+        Entity::insert(model)
+            .exec_without_returning(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    // records of the series overlapping [from, to), oldest first
+    pub async fn list(
+        &self,
+        series_id: SeriesId,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Result<Vec<Model>, DbErr> {
+        let mut query = Entity::find().filter(Column::SeriesId.eq(series_id.0.get() as i64));
+        if let Some(from) = from {
+            query = query.filter(Column::ToTs.gt(from as i64));
+        }
+        if let Some(to) = to {
+            query = query.filter(Column::FromTs.lt(to as i64));
+        }
+
+        query.order_by_asc(Column::Id).all(&self.db).await
+    }
+
+    // object keys named by any record, old and new versions alike
+    pub async fn referenced_keys(&self) -> Result<HashSet<String>, DbErr> {
+        let keys: Vec<(Option<String>, String)> = Entity::find()
+            .select_only()
+            .column(Column::PreviousObjectKey)
+            .column(Column::ObjectKey)
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+
+        Ok(keys
+            .into_iter()
+            .flat_map(|(previous, key)| previous.into_iter().chain([key]))
+            .collect())
+    }
+}
@@ -0,0 +1,175 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{QueryFilter, Set};
+
+use vodnik_core::meta::{SeriesId, WriteStats};
+
+// client batch ids of recent writes. a batch with a known id isn't written again, the stored
+// result is returned instead. ids are scoped per series. an id is reserved before its batch is
+// written, the result columns are NULL until the write is done.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "ingest_batches")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub series_id: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub batch_id: String,
+
+    pub samples: Option<i64>,
+    pub merged: Option<i64>,
+    pub rejected: Option<i64>,
+    // operation the batch was written under
+    pub operation: Option<String>,
+
+    // ms after UNIX EPOCH
+    pub created_at: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+// outcome of `BatchIdStore::reserve`
+#[derive(Debug)]
+pub enum Reservation {
+    // the id is new (or expired), the batch has to be written
+    Reserved,
+    // written before, with the stored result and operation
+    Written(WriteStats, Option<String>),
+    // another request is writing the batch right now
+    Pending,
+}
+
+#[derive(Clone, Debug)]
+pub struct BatchIdStore {
+    db: DatabaseConnection,
+    // ids older than this are forgotten
+    ttl_ms: u64,
+}
+
+impl BatchIdStore {
+    pub fn new(db: DatabaseConnection, ttl_ms: u64) -> Self {
+        Self { db, ttl_ms }
+    }
+
+    // claims the id for a write under `operation`. only one of concurrent requests with the
+    // same id gets `Reserved`.
+    pub async fn reserve(
+        &self,
+        series_id: SeriesId,
+        batch_id: &str,
+        operation: Option<&str>,
+        now_ms: u64,
+    ) -> Result<Reservation, DbErr> {
+        let key = (series_id.0.get() as i64, batch_id.to_string());
+        let model = ActiveModel {
+            series_id: Set(key.0),
+            batch_id: Set(key.1.clone()),
+            samples: Set(None),
+            merged: Set(None),
+            rejected: Set(None),
+            operation: Set(operation.map(str::to_string)),
+            created_at: Set(now_ms as i64),
+        };
+        let inserted = Entity::insert(model)
+            .on_conflict(
+                OnConflict::columns([Column::SeriesId, Column::BatchId])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+        if inserted == 1 {
+            return Ok(Reservation::Reserved);
+        }
+
+        // an expired id is reused
+        let taken = Entity::update_many()
+            .col_expr(Column::Samples, Expr::value(Option::<i64>::None))
+            .col_expr(Column::Merged, Expr::value(Option::<i64>::None))
+            .col_expr(Column::Rejected, Expr::value(Option::<i64>::None))
+            .col_expr(
+                Column::Operation,
+                Expr::value(operation.map(str::to_string)),
+            )
+            .col_expr(Column::CreatedAt, Expr::value(now_ms as i64))
+            .filter(Column::SeriesId.eq(key.0))
+            .filter(Column::BatchId.eq(key.1.clone()))
+            .filter(Column::CreatedAt.lte(self.expired_before(now_ms)))
+            .exec(&self.db)
+            .await?;
+        if taken.rows_affected == 1 {
+            return Ok(Reservation::Reserved);
+        }
+
+        let Some(model) = Entity::find_by_id(key).one(&self.db).await? else {
+            // purged in between
+            return Ok(Reservation::Pending);
+        };
+        Ok(match (model.samples, model.merged, model.rejected) {
+            (Some(samples), Some(merged), Some(rejected)) => Reservation::Written(
+                WriteStats {
+                    samples: samples as u64,
+                    merged: merged as u64,
+                    rejected: rejected as u64,
+                },
+                model.operation,
+            ),
+            _ => Reservation::Pending,
+        })
+    }
+
+    // stores the result of a reserved batch
+    pub async fn complete(
+        &self,
+        series_id: SeriesId,
+        batch_id: &str,
+        stats: &WriteStats,
+    ) -> Result<(), DbErr> {
+        Entity::update_many()
+            .col_expr(Column::Samples, Expr::value(stats.samples as i64))
+            .col_expr(Column::Merged, Expr::value(stats.merged as i64))
+            .col_expr(Column::Rejected, Expr::value(stats.rejected as i64))
+            .filter(Column::SeriesId.eq(series_id.0.get() as i64))
+            .filter(Column::BatchId.eq(batch_id))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    // gives up a reservation after a failed write, a retry writes the batch
+    pub async fn release(&self, series_id: SeriesId, batch_id: &str) -> Result<(), DbErr> {
+        Entity::delete_many()
+            .filter(Column::SeriesId.eq(series_id.0.get() as i64))
+            .filter(Column::BatchId.eq(batch_id))
+            .filter(Column::Samples.is_null())
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    // drops the reservations of writes interrupted by a restart, returns how many
+    pub async fn release_pending(&self) -> Result<u64, DbErr> {
+        let res = Entity::delete_many()
+            .filter(Column::Samples.is_null())
+            .exec(&self.db)
+            .await?;
+
+        Ok(res.rows_affected)
+    }
+
+    // drops expired ids, returns how many
+    pub async fn purge(&self, now_ms: u64) -> Result<u64, DbErr> {
+        let res = Entity::delete_many()
+            .filter(Column::CreatedAt.lte(self.expired_before(now_ms)))
+            .exec(&self.db)
+            .await?;
+
+        Ok(res.rows_affected)
+    }
+
+    fn expired_before(&self, now_ms: u64) -> i64 {
+        now_ms.saturating_sub(self.ttl_ms) as i64
+    }
+}
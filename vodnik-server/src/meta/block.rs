@@ -1,13 +1,19 @@
 use sea_orm::ActiveValue::NotSet;
 use sea_orm::entity::prelude::*;
-use sea_orm::{QueryOrder, QuerySelect, Set};
+use sea_orm::sea_query::{Expr, Query};
+use sea_orm::{QueryOrder, QuerySelect, Set, TransactionTrait};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::TryInto;
 use thiserror::Error;
 
+use vodnik_core::aggregate::integral::BlockIntegral;
 use vodnik_core::meta::{
     BinaryAccumulator, BlockMeta, BlockNumber, Quality, SeriesId, StorableNum,
 };
+use vodnik_core::wal::TxId;
+
+use super::{version, version_operation};
 
 #[derive(Error, Debug)]
 pub enum BlockMetaStoreError {
@@ -62,6 +68,11 @@ pub struct Model {
     pub qual_acc_or: i64,
     pub qual_acc_and: i64,
 
+    #[sea_orm(column_type = "Double", nullable)]
+    pub area_step: Option<f64>,
+    #[sea_orm(column_type = "Double", nullable)]
+    pub area_linear: Option<f64>,
+
     pub object_key: String,
     pub created_at: i64,
 }
@@ -90,6 +101,7 @@ impl BlockMetaStore {
         block_id: BlockNumber,
         object_key: String,
         meta: &BlockMeta<T>,
+        integral: &BlockIntegral,
     ) -> Result<(), BlockMetaStoreError>
     where
         T: StorableNum,
@@ -128,6 +140,9 @@ impl BlockMetaStore {
             qual_acc_or: Set(meta.qual_acc_or as i64),
             qual_acc_and: Set(meta.qual_acc_and as i64),
 
+            area_step: Set(Some(integral.step)),
+            area_linear: Set(Some(integral.linear)),
+
             object_key: Set(object_key),
             created_at: NotSet, // let the DB handle that
         };
@@ -145,6 +160,8 @@ impl BlockMetaStore {
                         Column::CreatedAt,
                         Column::QualAccOr,
                         Column::QualAccAnd,
+                        Column::AreaStep,
+                        Column::AreaLinear,
                         Column::FstValidVal,
                         Column::FstValidQ,
                         Column::FstValidOffset,
@@ -166,13 +183,14 @@ impl BlockMetaStore {
         Ok(())
     }
 
-    /// Returns (BlockId, BlockMeta<T>) tuples.
+    /// Returns (BlockId, BlockMeta<T>, BlockIntegral) tuples. the integral is None for blocks
+    /// stored before it was tracked.
     pub async fn list_in_range<T>(
         &self,
         series_id: SeriesId,
         min_block_id: BlockNumber,
         max_block_id: BlockNumber,
-    ) -> Result<Vec<(BlockNumber, BlockMeta<T>)>, BlockMetaStoreError>
+    ) -> Result<Vec<(BlockNumber, BlockMeta<T>, Option<BlockIntegral>)>, BlockMetaStoreError>
     where
         T: StorableNum,
         T::Accumulator: BinaryAccumulator,
@@ -192,7 +210,11 @@ impl BlockMetaStore {
         let mut results = Vec::with_capacity(models.len());
         for m in models {
             let meta = Self::model_to_meta(&m)?;
-            results.push((BlockNumber(m.block_id as u64), meta));
+            let integral = match (m.area_step, m.area_linear) {
+                (Some(step), Some(linear)) => Some(BlockIntegral { step, linear }),
+                _ => None,
+            };
+            results.push((BlockNumber(m.block_id as u64), meta, integral));
         }
 
         Ok(results)
@@ -227,6 +249,53 @@ impl BlockMetaStore {
         }
     }
 
+    /// Returns the closest block before `block_id` holding at least one valid sample.
+    /// blocks without valid samples are skipped without decoding them.
+    pub async fn last_valid_before<T>(
+        &self,
+        series_id: SeriesId,
+        block_id: BlockNumber,
+    ) -> Result<Option<(BlockNumber, BlockMeta<T>)>, BlockMetaStoreError>
+    where
+        T: StorableNum,
+        T::Accumulator: BinaryAccumulator,
+    {
+        let model = Entity::find()
+            .filter(Column::SeriesId.eq(series_id.0.get() as i64))
+            .filter(Column::BlockId.lt(block_id.0 as i64))
+            .filter(Column::CountValid.gt(0))
+            .order_by_desc(Column::BlockId)
+            .one(&self.db)
+            .await?;
+
+        model
+            .map(|m| Ok((BlockNumber(m.block_id as u64), Self::model_to_meta(&m)?)))
+            .transpose()
+    }
+
+    /// Returns the closest block after `block_id` holding at least one valid sample.
+    pub async fn first_valid_after<T>(
+        &self,
+        series_id: SeriesId,
+        block_id: BlockNumber,
+    ) -> Result<Option<(BlockNumber, BlockMeta<T>)>, BlockMetaStoreError>
+    where
+        T: StorableNum,
+        T::Accumulator: BinaryAccumulator,
+    {
+        let model = Entity::find()
+            .filter(Column::SeriesId.eq(series_id.0.get() as i64))
+            .filter(Column::BlockId.gt(block_id.0 as i64))
+            .filter(Column::CountValid.gt(0))
+            .order_by_asc(Column::BlockId)
+            .one(&self.db)
+            .await?;
+
+        model
+            .map(|m| Ok((BlockNumber(m.block_id as u64), Self::model_to_meta(&m)?)))
+            .transpose()
+    }
+
     pub async fn get_object_key(
         &self,
         series_id: SeriesId,
@@ -250,6 +319,224 @@ impl BlockMetaStore {
         ))
     }
 
+    /// Records a stored version of a block together with the operations that wrote into it,
+    /// see `version::Model`.
+    pub async fn add_version(
+        &self,
+        series_id: SeriesId,
+        block_id: BlockNumber,
+        object_key: &str,
+        tx: TxId,
+        operations: &[&str],
+        created_at: u64,
+    ) -> Result<(), BlockMetaStoreError> {
+        let model = version::ActiveModel {
+            id: NotSet,
+            series_id: Set(series_id.0.get() as i64),
+            block_id: Set(block_id.0 as i64),
+            object_key: Set(object_key.to_string()),
+            created_at: Set(created_at as i64),
+            tx: Set(tx.0 as i64),
+        };
+
+        let txn = self.db.begin().await?;
+        let version_id = version::Entity::insert(model)
+            .exec(&txn)
+            .await?
+            .last_insert_id;
+        if !operations.is_empty() {
+            let ops = operations.iter().map(|op| version_operation::ActiveModel {
+                version_id: Set(version_id),
+                operation: Set((*op).to_owned()),
+            });
+            version_operation::Entity::insert_many(ops)
+                .exec_without_returning(&txn)
+                .await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// Returns (BlockId, object key) of the latest version stored at or before `as_of` for
+    /// every block in the range. blocks created later are left out.
+    pub async fn versions_as_of(
+        &self,
+        series_id: SeriesId,
+        min_block_id: BlockNumber,
+        max_block_id: BlockNumber,
+        as_of: u64,
+    ) -> Result<Vec<(BlockNumber, String)>, BlockMetaStoreError> {
+        // TODO: let the DB pick the latest version per block, once histories get long
+        let models = version::Entity::find()
+            .filter(version::Column::SeriesId.eq(series_id.0.get() as i64))
+            .filter(version::Column::BlockId.gte(min_block_id.0 as i64))
+            .filter(version::Column::BlockId.lte(max_block_id.0 as i64))
+            .filter(version::Column::CreatedAt.lte(as_of as i64))
+            .order_by_asc(version::Column::BlockId)
+            .order_by_asc(version::Column::Id)
+            .all(&self.db)
+            .await?;
+
+        let mut results: Vec<(BlockNumber, String)> = vec![];
+        for m in models {
+            let id = BlockNumber(m.block_id as u64);
+            match results.last_mut() {
+                Some(last) if last.0 == id => last.1 = m.object_key,
+                _ => results.push((id, m.object_key)),
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Returns the object keys of all blocks and of all recorded block versions.
+    pub async fn referenced_keys(&self) -> Result<HashSet<String>, BlockMetaStoreError> {
+        let current: Vec<String> = Entity::find()
+            .select_only()
+            .column(Column::ObjectKey)
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+        let versions: Vec<String> = version::Entity::find()
+            .select_only()
+            .column(version::Column::ObjectKey)
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+
+        Ok(current.into_iter().chain(versions).collect())
+    }
+
+    /// Drops the versions created before `before` (ms after UNIX EPOCH). The latest of them is
+    /// kept per block, point in time queries at `before` and later still find it. Returns how
+    /// many versions were dropped.
+    pub async fn prune_versions(&self, before: u64) -> Result<u64, BlockMetaStoreError> {
+        let old: Vec<(i64, i64, i64)> = version::Entity::find()
+            .select_only()
+            .column(version::Column::Id)
+            .column(version::Column::SeriesId)
+            .column(version::Column::BlockId)
+            .filter(version::Column::CreatedAt.lt(before as i64))
+            .order_by_desc(version::Column::Id)
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+
+        let mut latest: HashMap<(i64, i64), i64> = HashMap::new();
+        let mut prunable = vec![];
+        for (id, series_id, block_id) in old {
+            if latest.insert((series_id, block_id), id).is_some() {
+                prunable.push(id);
+            }
+        }
+
+        let mut pruned = 0;
+        for ids in prunable.chunks(500) {
+            let txn = self.db.begin().await?;
+            version_operation::Entity::delete_many()
+                .filter(version_operation::Column::VersionId.is_in(ids.iter().copied()))
+                .exec(&txn)
+                .await?;
+            let res = version::Entity::delete_many()
+                .filter(version::Column::Id.is_in(ids.iter().copied()))
+                .exec(&txn)
+                .await?;
+            txn.commit().await?;
+            pruned += res.rows_affected;
+        }
+
+        Ok(pruned)
+    }
+
+    /// Returns all versions an operation wrote into, oldest first.
+    pub async fn versions_of_operation(
+        &self,
+        operation: &str,
+    ) -> Result<Vec<version::Model>, BlockMetaStoreError> {
+        let ids = Query::select()
+            .column(version_operation::Column::VersionId)
+            .from(version_operation::Entity)
+            .and_where(Expr::col(version_operation::Column::Operation).eq(operation))
+            .to_owned();
+
+        Ok(version::Entity::find()
+            .filter(version::Column::Id.in_subquery(ids))
+            .order_by_asc(version::Column::Id)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Returns the latest version of the block written before the version `before_id`.
+    pub async fn version_before(
+        &self,
+        series_id: SeriesId,
+        block_id: BlockNumber,
+        before_id: i64,
+    ) -> Result<Option<version::Model>, BlockMetaStoreError> {
+        Ok(version::Entity::find()
+            .filter(version::Column::SeriesId.eq(series_id.0.get() as i64))
+            .filter(version::Column::BlockId.eq(block_id.0 as i64))
+            .filter(version::Column::Id.lt(before_id))
+            .order_by_desc(version::Column::Id)
+            .one(&self.db)
+            .await?)
+    }
+
+    /// Returns the operations that wrote into the version `version_id`.
+    pub async fn operations_of_version(
+        &self,
+        version_id: i64,
+    ) -> Result<BTreeSet<String>, BlockMetaStoreError> {
+        let ops: Vec<String> = version_operation::Entity::find()
+            .select_only()
+            .column(version_operation::Column::Operation)
+            .filter(version_operation::Column::VersionId.eq(version_id))
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+        Ok(ops.into_iter().collect())
+    }
+
+    /// Returns the operations that wrote into the block from the version `from_id` on.
+    pub async fn operations_since(
+        &self,
+        series_id: SeriesId,
+        block_id: BlockNumber,
+        from_id: i64,
+    ) -> Result<BTreeSet<String>, BlockMetaStoreError> {
+        let versions = Query::select()
+            .column(version::Column::Id)
+            .from(version::Entity)
+            .and_where(Expr::col(version::Column::SeriesId).eq(series_id.0.get() as i64))
+            .and_where(Expr::col(version::Column::BlockId).eq(block_id.0 as i64))
+            .and_where(Expr::col(version::Column::Id).gte(from_id))
+            .to_owned();
+
+        let ops: Vec<String> = version_operation::Entity::find()
+            .select_only()
+            .column(version_operation::Column::Operation)
+            .filter(version_operation::Column::VersionId.in_subquery(versions))
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+        Ok(ops.into_iter().collect())
+    }
+
+    /// Returns how many versions of the block were written after the version `after_id`.
+    pub async fn count_versions_after(
+        &self,
+        series_id: SeriesId,
+        block_id: BlockNumber,
+        after_id: i64,
+    ) -> Result<u64, BlockMetaStoreError> {
+        Ok(version::Entity::find()
+            .filter(version::Column::SeriesId.eq(series_id.0.get() as i64))
+            .filter(version::Column::BlockId.eq(block_id.0 as i64))
+            .filter(version::Column::Id.gt(after_id))
+            .count(&self.db)
+            .await?)
+    }
+
     // Internal mapping function
     fn model_to_meta<T>(m: &Model) -> Result<BlockMeta<T>, BlockMetaStoreError>
     where
@@ -0,0 +1,84 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::{Expr, IntoIden, Query, SelectStatement, SimpleExpr};
+use serde::Deserialize;
+
+// label index, one row per series label. kept in sync with `series.labels` by the SqlMetaStore
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "series_labels")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub series_id: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub name: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub value: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    // series must match all matchers
+    #[default]
+    All,
+    // series must match at least one matcher
+    Any,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelOp {
+    #[default]
+    Eq,
+    // series without the label value, including series without the label
+    Ne,
+    // label value matches the regex
+    Re,
+    // series has the label, any value
+    Exists,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LabelMatcher {
+    pub name: String,
+    #[serde(default)]
+    pub op: LabelOp,
+    // unused for `exists`
+    #[serde(default)]
+    pub value: String,
+}
+
+impl LabelMatcher {
+    // condition on `series.id`
+    pub(crate) fn condition(&self, series_id: impl IntoIden + 'static) -> SimpleExpr {
+        let ids = |value: Option<SimpleExpr>| -> SelectStatement {
+            let mut q = Query::select();
+            q.column(Column::SeriesId)
+                .from(Entity)
+                .and_where(Expr::col(Column::Name).eq(self.name.as_str()));
+            if let Some(v) = value {
+                q.and_where(v);
+            }
+            q.to_owned()
+        };
+
+        let col = Expr::col(series_id);
+        match self.op {
+            LabelOp::Eq => {
+                col.in_subquery(ids(Some(Expr::col(Column::Value).eq(self.value.as_str()))))
+            }
+            LabelOp::Ne => {
+                col.not_in_subquery(ids(Some(Expr::col(Column::Value).eq(self.value.as_str()))))
+            }
+            LabelOp::Re => col.in_subquery(ids(Some(Expr::cust_with_values(
+                "\"value\" REGEXP ?",
+                [self.value.as_str()],
+            )))),
+            LabelOp::Exists => col.in_subquery(ids(None)),
+        }
+    }
+}
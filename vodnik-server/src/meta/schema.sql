@@ -9,10 +9,61 @@ CREATE TABLE series (
     sample_res TEXT NOT NULL,
     first INTEGER NOT NULL,
     last INTEGER NOT NULL,
-    labels TEXT NOT NULL
+    labels TEXT NOT NULL,
+    timezone TEXT NOT NULL DEFAULT 'UTC',
+    alignment TEXT NOT NULL DEFAULT 'floor',
+    collision TEXT NOT NULL DEFAULT 'last_wins',
+    non_finite TEXT NOT NULL DEFAULT 'reject',
+    max_future_ms INTEGER NOT NULL DEFAULT 86400000
 );
 
 
+-- label index, maintained next to series.labels
+CREATE TABLE series_labels (
+    series_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (series_id, name, value)
+) WITHOUT ROWID;
+
+CREATE INDEX series_labels_name_value ON series_labels (name, value);
+
+-- existing databases: backfill the index from series.labels
+-- INSERT OR IGNORE INTO series_labels (series_id, name, value)
+--     SELECT s.id, l.key, l.value FROM series s, json_each(s.labels) l;
+
+-- client batch ids of recent writes, see meta::batch
+CREATE TABLE ingest_batches (
+    series_id INTEGER NOT NULL,
+    batch_id TEXT NOT NULL,
+    samples INTEGER, -- result columns are NULL while the batch is being written
+    merged INTEGER,
+    rejected INTEGER,
+    operation TEXT,
+    created_at INTEGER NOT NULL, -- ms after UNIX EPOCH
+    PRIMARY KEY (series_id, batch_id)
+) WITHOUT ROWID;
+
+CREATE INDEX ingest_batches_created_at ON ingest_batches (created_at);
+
+-- manual overwrites, one record per rewritten block, see meta::audit
+CREATE TABLE overwrite_audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    series_id INTEGER NOT NULL,
+    block_id INTEGER NOT NULL,
+    from_ts INTEGER NOT NULL, -- [from_ts, to_ts) in ms after UNIX EPOCH
+    to_ts INTEGER NOT NULL,
+    samples INTEGER NOT NULL,
+    user_name TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    previous_object_key TEXT, -- the replaced block version, kept in storage
+    object_key TEXT NOT NULL,
+    operation TEXT NOT NULL,
+    created_at INTEGER NOT NULL -- ms after UNIX EPOCH
+);
+
+CREATE INDEX overwrite_audit_series ON overwrite_audit (series_id, from_ts);
+
 CREATE TABLE blocks (
     series_id INTEGER NOT NULL,
     block_id INTEGER NOT NULL,
@@ -21,31 +72,61 @@ CREATE TABLE blocks (
     count_valid INTEGER NOT NULL,
 
     sum_val BLOB,       -- Serialized Accumulator (i128/u128/f64)
-    min_val NUMERIC,    
-    max_val NUMERIC,
+    min_val REAL,    
+    max_val REAL,
 
-    fst_valid_val NUMERIC,
+    fst_valid_val REAL,
     fst_valid_q INTEGER, 
     fst_valid_offset INTEGER,
 
-    lst_valid_val NUMERIC,
+    lst_valid_val REAL,
     lst_valid_q INTEGER,
     lst_valid_offset INTEGER,
 
-    fst_val NUMERIC,
+    fst_val REAL,
     fst_q INTEGER,
     fst_offset INTEGER,
 
-    lst_val NUMERIC,
+    lst_val REAL,
     lst_q INTEGER,
     lst_offset INTEGER,
 
     qual_acc_or INTEGER NOT NULL,
     qual_acc_and INTEGER NOT NULL,
 
+    -- partial time integrals between fst_valid and lst_valid, in value * sample slots
+    area_step REAL,
+    area_linear REAL,
+
     -- Storage Pointer & System Meta
     object_key TEXT NOT NULL, 
     created_at INTEGER NOT NULL DEFAULT (unixepoch()),
 
     PRIMARY KEY (series_id, block_id)
 ) WITHOUT ROWID;
+
+-- every stored block version, see meta::version. blocks.object_key is the latest one
+CREATE TABLE block_versions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    series_id INTEGER NOT NULL,
+    block_id INTEGER NOT NULL,
+    object_key TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- ms after UNIX EPOCH
+    tx INTEGER NOT NULL
+);
+
+CREATE INDEX block_versions_block ON block_versions (series_id, block_id, created_at);
+
+-- requests that wrote into a block version, see OPERATION_HEADER. a block flushed from memory
+-- holds the samples of every request since it was created
+CREATE TABLE block_version_operations (
+    version_id INTEGER NOT NULL,
+    operation TEXT NOT NULL,
+    PRIMARY KEY (version_id, operation)
+) WITHOUT ROWID;
+
+CREATE INDEX block_version_operations_operation ON block_version_operations (operation);
+
+-- existing databases: backfill the current version of each block
+-- INSERT INTO block_versions (series_id, block_id, object_key, created_at, tx)
+--     SELECT series_id, block_id, object_key, created_at * 1000, 0 FROM blocks;
@@ -3,17 +3,35 @@ use std::{collections::BTreeMap, num::NonZero};
 use crate::meta::*;
 
 use sea_orm::{
-    ActiveValue::Set, Database, FromJsonQueryResult, IntoActiveModel, entity::prelude::*,
+    ActiveValue::Set, Condition, ConnectOptions, ConnectionTrait, Database, FromJsonQueryResult,
+    IntoActiveModel, Order, QueryOrder, QuerySelect, TransactionTrait, entity::prelude::*,
 };
 use serde::ser::SerializeMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use tracing::info;
 use vodnik_core::meta::{
-    BlockLength, BlockNumber, Label, NonEmptySlice, SampleLength, SeriesMeta, StorageType,
-    TimeResolution,
+    AlignPolicy, BlockLength, BlockNumber, CollisionPolicy, Label, NonFinitePolicy, SampleLength,
+    SeriesMeta, StorageType, TimeResolution,
 };
 
+use crate::meta::label::{self, LabelMatcher, MatchMode};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    #[default]
+    Id,
+    Name,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub sort: SortBy,
+    pub descending: bool,
+    pub limit: u64,
+    pub offset: u64,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "Text")]
 pub enum DbStorageType {
@@ -96,6 +114,103 @@ impl From<DbTimeResolution> for TimeResolution {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Text")]
+pub enum DbAlignPolicy {
+    #[sea_orm(string_value = "floor")]
+    Floor,
+    #[sea_orm(string_value = "nearest")]
+    Nearest,
+    #[sea_orm(string_value = "reject")]
+    Reject,
+}
+
+impl From<AlignPolicy> for DbAlignPolicy {
+    fn from(v: AlignPolicy) -> Self {
+        match v {
+            AlignPolicy::Floor => Self::Floor,
+            AlignPolicy::Nearest => Self::Nearest,
+            AlignPolicy::Reject => Self::Reject,
+        }
+    }
+}
+
+impl From<DbAlignPolicy> for AlignPolicy {
+    fn from(v: DbAlignPolicy) -> Self {
+        match v {
+            DbAlignPolicy::Floor => Self::Floor,
+            DbAlignPolicy::Nearest => Self::Nearest,
+            DbAlignPolicy::Reject => Self::Reject,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Text")]
+pub enum DbCollisionPolicy {
+    #[sea_orm(string_value = "last_wins")]
+    LastWins,
+    #[sea_orm(string_value = "first_wins")]
+    FirstWins,
+    #[sea_orm(string_value = "better_quality")]
+    BetterQuality,
+    #[sea_orm(string_value = "average")]
+    Average,
+    #[sea_orm(string_value = "reject")]
+    Reject,
+}
+
+impl From<CollisionPolicy> for DbCollisionPolicy {
+    fn from(v: CollisionPolicy) -> Self {
+        match v {
+            CollisionPolicy::LastWins => Self::LastWins,
+            CollisionPolicy::FirstWins => Self::FirstWins,
+            CollisionPolicy::BetterQuality => Self::BetterQuality,
+            CollisionPolicy::Average => Self::Average,
+            CollisionPolicy::Reject => Self::Reject,
+        }
+    }
+}
+
+impl From<DbCollisionPolicy> for CollisionPolicy {
+    fn from(v: DbCollisionPolicy) -> Self {
+        match v {
+            DbCollisionPolicy::LastWins => Self::LastWins,
+            DbCollisionPolicy::FirstWins => Self::FirstWins,
+            DbCollisionPolicy::BetterQuality => Self::BetterQuality,
+            DbCollisionPolicy::Average => Self::Average,
+            DbCollisionPolicy::Reject => Self::Reject,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Text")]
+pub enum DbNonFinitePolicy {
+    #[sea_orm(string_value = "reject")]
+    Reject,
+    #[sea_orm(string_value = "store_bad")]
+    StoreBad,
+}
+
+impl From<NonFinitePolicy> for DbNonFinitePolicy {
+    fn from(v: NonFinitePolicy) -> Self {
+        match v {
+            NonFinitePolicy::Reject => Self::Reject,
+            NonFinitePolicy::StoreBad => Self::StoreBad,
+        }
+    }
+}
+
+impl From<DbNonFinitePolicy> for NonFinitePolicy {
+    fn from(v: DbNonFinitePolicy) -> Self {
+        match v {
+            DbNonFinitePolicy::Reject => Self::Reject,
+            DbNonFinitePolicy::StoreBad => Self::StoreBad,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, FromJsonQueryResult)]
 pub struct DbLabels(pub Vec<Label>);
 
@@ -140,6 +255,11 @@ pub struct Model {
     pub first: i64,
     pub last: i64,
     pub labels: DbLabels,
+    pub timezone: String,
+    pub alignment: DbAlignPolicy,
+    pub collision: DbCollisionPolicy,
+    pub non_finite: DbNonFinitePolicy,
+    pub max_future_ms: i64,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -159,7 +279,11 @@ impl SqlMetaStore {
 }
 
 pub async fn create(db_url: &str) -> Result<DatabaseConnection, MetaStoreError> {
-    match Database::connect(db_url).await {
+    let mut opts = ConnectOptions::new(db_url);
+    // label selectors use REGEXP, which sqlite doesn't ship by default
+    opts.map_sqlx_sqlite_opts(|o| o.with_regexp());
+
+    match Database::connect(opts).await {
         Ok(_db) => {
             info!("Connected to metadata database at {}", db_url);
             Ok(_db)
@@ -180,6 +304,11 @@ fn model_to_meta(m: Model) -> SeriesMeta {
         first_block: BlockNumber(m.first as u64),
         last_block: BlockNumber(m.last as u64),
         labels: m.labels.0,
+        timezone: m.timezone,
+        alignment: m.alignment.into(),
+        collision: m.collision.into(),
+        non_finite: m.non_finite.into(),
+        max_future_ms: m.max_future_ms as u64,
     }
 }
 
@@ -200,13 +329,18 @@ impl SqlMetaStore {
             first: Set(series.first_block.0 as i64),
             last: Set(series.last_block.0 as i64),
             labels: Set(DbLabels(series.labels.clone())),
+            timezone: Set(series.timezone.clone()),
+            alignment: Set(series.alignment.into()),
+            collision: Set(series.collision.into()),
+            non_finite: Set(series.non_finite.into()),
+            max_future_ms: Set(series.max_future_ms as i64),
             ..Default::default()
         };
 
-        let res = Entity::insert(active)
-            .exec(&self.db)
-            .await
-            .map_err(orm_err)?;
+        let txn = self.db.begin().await.map_err(orm_err)?;
+        let res = Entity::insert(active).exec(&txn).await.map_err(orm_err)?;
+        write_labels(&txn, res.last_insert_id, &series.labels).await?;
+        txn.commit().await.map_err(orm_err)?;
 
         Ok(SeriesId(NonZero::new(res.last_insert_id as u64).unwrap()))
     }
@@ -220,11 +354,6 @@ impl SqlMetaStore {
 
         Ok(model_to_meta(model))
     }
-    pub(crate) async fn get_all(&self) -> Result<Vec<SeriesMeta>, MetaStoreError> {
-        let models = Entity::find().all(&self.db).await.map_err(orm_err)?;
-
-        Ok(models.into_iter().map(model_to_meta).collect())
-    }
 
     pub(crate) async fn update(&self, series: &SeriesMeta) -> Result<(), MetaStoreError> {
         let mut model = Entity::find_by_id(series.id.0.get() as i64)
@@ -243,14 +372,23 @@ impl SqlMetaStore {
         model.first = Set(series.first_block.0 as i64);
         model.last = Set(series.last_block.0 as i64);
         model.labels = Set(DbLabels(series.labels.clone()));
+        model.timezone = Set(series.timezone.clone());
+        model.alignment = Set(series.alignment.into());
+        model.collision = Set(series.collision.into());
+        model.non_finite = Set(series.non_finite.into());
+        model.max_future_ms = Set(series.max_future_ms as i64);
 
-        model.update(&self.db).await.map_err(orm_err)?;
+        let txn = self.db.begin().await.map_err(orm_err)?;
+        model.update(&txn).await.map_err(orm_err)?;
+        write_labels(&txn, series.id.0.get() as i64, &series.labels).await?;
+        txn.commit().await.map_err(orm_err)?;
 
         Ok(())
     }
     pub(crate) async fn delete(&self, id: SeriesId) -> Result<(), MetaStoreError> {
+        let txn = self.db.begin().await.map_err(orm_err)?;
         let res = Entity::delete_by_id(id.0.get() as i64)
-            .exec(&self.db)
+            .exec(&txn)
             .await
             .map_err(orm_err)?;
 
@@ -258,35 +396,110 @@ impl SqlMetaStore {
             return Err(MetaStoreError::NotFound(id));
         }
 
+        write_labels(&txn, id.0.get() as i64, &[]).await?;
+        txn.commit().await.map_err(orm_err)?;
+
         Ok(())
     }
-    pub(crate) async fn match_any(
-        &self,
-        labels: NonEmptySlice<'_, Label>,
-    ) -> Result<Vec<SeriesMeta>, MetaStoreError> {
-        let wanted = labels.as_slice();
 
-        let models = Entity::find().all(&self.db).await.map_err(orm_err)?;
+    // series names aren't unique, all series with the name
+    pub(crate) async fn find_by_name(&self, name: &str) -> Result<Vec<SeriesMeta>, MetaStoreError> {
+        let models = Entity::find()
+            .filter(Column::Name.eq(name))
+            .order_by_asc(Column::Id)
+            .all(&self.db)
+            .await
+            .map_err(orm_err)?;
 
-        Ok(models
-            .into_iter()
-            .filter(|m| wanted.iter().any(|l| m.labels.0.iter().any(|x| x == l)))
-            .map(model_to_meta)
-            .collect())
+        Ok(models.into_iter().map(model_to_meta).collect())
     }
 
-    pub(crate) async fn match_all(
+    // series matching the label matchers. without matchers all series match.
+    pub(crate) async fn find(
         &self,
-        labels: NonEmptySlice<'_, Label>,
+        matchers: &[LabelMatcher],
+        mode: MatchMode,
     ) -> Result<Vec<SeriesMeta>, MetaStoreError> {
-        let wanted = labels.as_slice();
+        let models = Entity::find()
+            .filter(selector_condition(matchers, mode))
+            .order_by_asc(Column::Id)
+            .all(&self.db)
+            .await
+            .map_err(orm_err)?;
+
+        Ok(models.into_iter().map(model_to_meta).collect())
+    }
 
-        let models = Entity::find().all(&self.db).await.map_err(orm_err)?;
+    // one page of the series matching the label matchers, together with the number of all
+    // matching series
+    pub(crate) async fn find_page(
+        &self,
+        matchers: &[LabelMatcher],
+        mode: MatchMode,
+        page: &Page,
+    ) -> Result<(Vec<SeriesMeta>, u64), MetaStoreError> {
+        let query = Entity::find().filter(selector_condition(matchers, mode));
+        let total = query.clone().count(&self.db).await.map_err(orm_err)?;
+
+        let order = if page.descending {
+            Order::Desc
+        } else {
+            Order::Asc
+        };
+        let query = match page.sort {
+            SortBy::Id => query.order_by(Column::Id, order),
+            SortBy::Name => query
+                .order_by(Column::Name, order.clone())
+                .order_by(Column::Id, order),
+        };
 
-        Ok(models
-            .into_iter()
-            .filter(|m| wanted.iter().all(|l| m.labels.0.iter().any(|x| x == l)))
-            .map(model_to_meta)
-            .collect())
+        let models = query
+            .offset(page.offset)
+            .limit(page.limit)
+            .all(&self.db)
+            .await
+            .map_err(orm_err)?;
+
+        Ok((models.into_iter().map(model_to_meta).collect(), total))
     }
 }
+
+fn selector_condition(matchers: &[LabelMatcher], mode: MatchMode) -> Condition {
+    let cond = match mode {
+        MatchMode::All => Condition::all(),
+        MatchMode::Any => Condition::any(),
+    };
+    matchers
+        .iter()
+        .fold(cond, |cond, m| cond.add(m.condition(Column::Id)))
+}
+
+// replaces the label index rows of a series
+async fn write_labels<C: ConnectionTrait>(
+    db: &C,
+    id: i64,
+    labels: &[Label],
+) -> Result<(), MetaStoreError> {
+    label::Entity::delete_many()
+        .filter(label::Column::SeriesId.eq(id))
+        .exec(db)
+        .await
+        .map_err(orm_err)?;
+
+    if labels.is_empty() {
+        return Ok(());
+    }
+
+    let rows = labels.iter().map(|l| label::ActiveModel {
+        series_id: Set(id),
+        name: Set(l.name.clone()),
+        value: Set(l.value.clone()),
+    });
+    label::Entity::insert_many(rows)
+        .on_conflict_do_nothing()
+        .exec(db)
+        .await
+        .map_err(orm_err)?;
+
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use sea_orm::entity::prelude::*;
+
+// every stored version of a block. `blocks` only points to the latest one, older versions are
+// kept for point in time queries until the gc drops them (VODNIK_VERSION_RETENTION_DAYS).
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "block_versions")]
+pub struct Model {
+    // increases with every flush, orders versions written in the same ms
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub series_id: i64,
+    pub block_id: i64,
+    pub object_key: String,
+    // ms after UNIX EPOCH
+    pub created_at: i64,
+    // largest tx contained in the version
+    pub tx: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
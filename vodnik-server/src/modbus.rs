@@ -0,0 +1,308 @@
+// Modbus TCP polling collector, enabled with the `modbus` feature and configured via the JSON
+// file in VODNIK_MODBUS_CONFIG. every point is polled at the sample interval of its series and
+// written through the regular ingest path. failed polls are written as bad samples.
+//
+// local run against a simulator (diagslave / modpoll from modbusdriver.com):
+//   diagslave -m tcp -p 5020
+//   modpoll -m tcp -p 5020 -r 1 -t 4:float 127.0.0.1 21.5   (writes holding registers 0 and 1)
+//   VODNIK_MODBUS_CONFIG=modbus.json cargo run -p vodnik-server --features modbus
+// with modbus.json:
+//   {"devices": [{"address": "127.0.0.1:5020", "points": [{"series": 14, "register": 0}]}]}
+// decoding and framing are covered by `cargo test -p vodnik-server --features modbus`.
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use tokio::{net::TcpStream, time::Instant};
+use tracing::{error, info, warn};
+use vodnik_core::{
+    api::{BatchIngest, ValueVec},
+    helpers,
+    meta::{Quality, SeriesId, SeriesMeta, StorageType},
+};
+
+use crate::{AppState, ingest::write_batch};
+
+mod client;
+
+use client::{ModbusClient, ModbusError};
+
+#[derive(Debug, Deserialize)]
+pub struct ModbusConfig {
+    pub devices: Vec<DeviceConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceConfig {
+    // host:port of the modbus tcp server
+    pub address: String,
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    pub points: Vec<PointConfig>,
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PointConfig {
+    pub series: SeriesId,
+    // address of the first register, 0 based
+    pub register: u16,
+    #[serde(default)]
+    pub kind: RegisterKind,
+    #[serde(default)]
+    pub word_order: WordOrder,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegisterKind {
+    #[default]
+    Holding,
+    Input,
+}
+
+// order of the 16 bit registers of values spanning multiple registers
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WordOrder {
+    // high word first
+    #[default]
+    Big,
+    // low word first
+    Little,
+}
+
+impl ModbusConfig {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+}
+
+struct Point {
+    config: PointConfig,
+    series: SeriesMeta,
+    sample_ms: u64,
+    next: Instant,
+}
+
+pub fn spawn(config: ModbusConfig, state: AppState) {
+    for device in config.devices {
+        let state = state.clone();
+        tokio::spawn(async move { poll_device(device, state).await });
+    }
+}
+
+async fn poll_device(device: DeviceConfig, state: AppState) {
+    let mut points = vec![];
+    for config in device.points {
+        match state.meta_store.get(config.series).await {
+            Ok(series) => {
+                let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+                points.push(Point {
+                    config,
+                    series,
+                    sample_ms,
+                    next: Instant::now(),
+                });
+            }
+            Err(e) => error!("modbus {}: point skipped: {e}", device.address),
+        }
+    }
+    if points.is_empty() {
+        return;
+    }
+    info!("modbus {}: polling {} points", device.address, points.len());
+
+    let timeout = Duration::from_millis(device.timeout_ms);
+    let mut client: Option<ModbusClient> = None;
+
+    loop {
+        let Some(due) = points.iter().map(|p| p.next).min() else {
+            return;
+        };
+        tokio::time::sleep_until(due).await;
+
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+
+        for point in points.iter_mut().filter(|p| p.next <= Instant::now()) {
+            // samples sit on the grid of the series
+            let ts = now_ms / point.sample_ms * point.sample_ms;
+            let next_ts = ts + point.sample_ms;
+            point.next = Instant::now() + Duration::from_millis(next_ts - now_ms);
+
+            if client.is_none() {
+                client = match tokio::time::timeout(timeout, TcpStream::connect(&device.address))
+                    .await
+                {
+                    Ok(Ok(stream)) => Some(ModbusClient::new(stream, device.unit_id, timeout)),
+                    Ok(Err(e)) => {
+                        warn!("modbus {}: connect failed: {e}", device.address);
+                        None
+                    }
+                    Err(_) => {
+                        warn!("modbus {}: connect timed out", device.address);
+                        None
+                    }
+                };
+            }
+
+            let (vals, q) = match &mut client {
+                Some(c) => match read_point(c, point).await {
+                    Ok(vals) => (vals, Quality::GOOD),
+                    Err(e) => {
+                        warn!(
+                            "modbus {}: register {} of series {}: {e}",
+                            device.address, point.config.register, point.series.id
+                        );
+                        // the connection is in an unknown state after io errors
+                        if !matches!(e, ModbusError::Exception(_) | ModbusError::Decode(_)) {
+                            client = None;
+                        }
+                        (bad_value(point.series.storage_type), e.quality())
+                    }
+                },
+                None => (
+                    bad_value(point.series.storage_type),
+                    Quality::BAD_COMM_FAILURE,
+                ),
+            };
+
+            let batch = BatchIngest {
+                series: point.series.id,
+                ts: vec![ts],
+                qs: vec![q],
+                batch_id: None,
+                vals,
+            };
+            if let Err(e) = write_batch(&state, &point.series, batch, None).await {
+                error!(
+                    "modbus {}: write to series {} failed: {e}",
+                    device.address, point.series.id
+                );
+            }
+        }
+    }
+}
+
+async fn read_point(client: &mut ModbusClient, point: &Point) -> Result<ValueVec, ModbusError> {
+    let stype = point.series.storage_type;
+    let regs = client
+        .read_registers(
+            point.config.kind,
+            point.config.register,
+            register_count(stype),
+        )
+        .await?;
+    decode_registers(stype, point.config.word_order, regs)
+}
+
+// 16 bit registers holding a value, enumerations use a single one
+fn register_count(stype: StorageType) -> u16 {
+    (stype.sample_bytes() as u16 / 2).max(1)
+}
+
+fn decode_registers(
+    stype: StorageType,
+    word_order: WordOrder,
+    mut regs: Vec<u16>,
+) -> Result<ValueVec, ModbusError> {
+    if matches!(word_order, WordOrder::Little) {
+        regs.reverse();
+    }
+    let wide = regs.iter().fold(0u64, |acc, r| acc << 16 | *r as u64);
+
+    Ok(match stype {
+        StorageType::Float32 => ValueVec::F32(vec![f32::from_bits(wide as u32)]),
+        StorageType::Float64 => ValueVec::F64(vec![f64::from_bits(wide)]),
+        StorageType::Int32 => ValueVec::I32(vec![wide as u32 as i32]),
+        StorageType::Int64 => ValueVec::I64(vec![wide as i64]),
+        StorageType::UInt32 => ValueVec::U32(vec![wide as u32]),
+        StorageType::UInt64 => ValueVec::U64(vec![wide]),
+        StorageType::Enumeration => {
+            let v = u8::try_from(wide).map_err(|_| {
+                ModbusError::Decode(format!("{wide} exceeds the enumeration range"))
+            })?;
+            ValueVec::Enum(vec![v])
+        }
+    })
+}
+
+// placeholder value of a bad sample
+fn bad_value(stype: StorageType) -> ValueVec {
+    match stype {
+        StorageType::Float32 => ValueVec::F32(vec![0.0]),
+        StorageType::Float64 => ValueVec::F64(vec![0.0]),
+        StorageType::Int32 => ValueVec::I32(vec![0]),
+        StorageType::Int64 => ValueVec::I64(vec![0]),
+        StorageType::UInt32 => ValueVec::U32(vec![0]),
+        StorageType::UInt64 => ValueVec::U64(vec![0]),
+        StorageType::Enumeration => ValueVec::Enum(vec![0]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(bytes: &[u8]) -> Vec<u16> {
+        bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn register_counts() {
+        assert_eq!(register_count(StorageType::Float32), 2);
+        assert_eq!(register_count(StorageType::Float64), 4);
+        assert_eq!(register_count(StorageType::Int64), 4);
+        assert_eq!(register_count(StorageType::Enumeration), 1);
+    }
+
+    #[test]
+    fn word_order() {
+        let big = words(&21.5f32.to_be_bytes());
+        let mut little = big.clone();
+        little.reverse();
+
+        for (order, regs) in [(WordOrder::Big, big), (WordOrder::Little, little)] {
+            let vals = decode_registers(StorageType::Float32, order, regs).unwrap();
+            assert!(matches!(vals, ValueVec::F32(v) if v == [21.5]));
+        }
+
+        let regs = words(&(-1234567890123i64).to_be_bytes());
+        let vals = decode_registers(StorageType::Int64, WordOrder::Big, regs).unwrap();
+        assert!(matches!(vals, ValueVec::I64(v) if v == [-1234567890123]));
+
+        let regs = words(&(-7i32).to_be_bytes());
+        let vals = decode_registers(StorageType::Int32, WordOrder::Big, regs).unwrap();
+        assert!(matches!(vals, ValueVec::I32(v) if v == [-7]));
+
+        let regs = words(&1.25f64.to_be_bytes());
+        let vals = decode_registers(StorageType::Float64, WordOrder::Big, regs).unwrap();
+        assert!(matches!(vals, ValueVec::F64(v) if v == [1.25]));
+    }
+
+    #[test]
+    fn enumeration_range() {
+        let vals = decode_registers(StorageType::Enumeration, WordOrder::Big, vec![255]).unwrap();
+        assert!(matches!(vals, ValueVec::Enum(v) if v == [255]));
+
+        let err = decode_registers(StorageType::Enumeration, WordOrder::Big, vec![256]);
+        assert!(matches!(err, Err(ModbusError::Decode(_))));
+    }
+}
@@ -0,0 +1,245 @@
+// minimal Modbus TCP client, read holding / input registers only
+
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use vodnik_core::meta::Quality;
+
+use super::RegisterKind;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+
+#[derive(Debug, Error)]
+pub enum ModbusError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("timeout")]
+    Timeout,
+    #[error("exception code {0:#04x}")]
+    Exception(u8),
+    #[error("invalid response: {0}")]
+    Protocol(String),
+    #[error("can't decode value: {0}")]
+    Decode(String),
+}
+
+impl ModbusError {
+    // quality of the sample written instead of the value
+    pub fn quality(&self) -> Quality {
+        match self {
+            ModbusError::Io(_) | ModbusError::Timeout | ModbusError::Protocol(_) => {
+                Quality::BAD_COMM_FAILURE
+            }
+            ModbusError::Exception(_) => Quality::BAD_DEVICE_FAILURE,
+            ModbusError::Decode(_) => Quality::BAD_CONFIG_ERROR,
+        }
+    }
+}
+
+pub struct ModbusClient {
+    stream: TcpStream,
+    unit_id: u8,
+    timeout: Duration,
+    transaction: u16,
+}
+
+impl ModbusClient {
+    pub fn new(stream: TcpStream, unit_id: u8, timeout: Duration) -> Self {
+        Self {
+            stream,
+            unit_id,
+            timeout,
+            transaction: 0,
+        }
+    }
+
+    pub async fn read_registers(
+        &mut self,
+        kind: RegisterKind,
+        start: u16,
+        count: u16,
+    ) -> Result<Vec<u16>, ModbusError> {
+        tokio::time::timeout(self.timeout, self.request(kind, start, count))
+            .await
+            .map_err(|_| ModbusError::Timeout)?
+    }
+
+    async fn request(
+        &mut self,
+        kind: RegisterKind,
+        start: u16,
+        count: u16,
+    ) -> Result<Vec<u16>, ModbusError> {
+        let function = match kind {
+            RegisterKind::Holding => READ_HOLDING_REGISTERS,
+            RegisterKind::Input => READ_INPUT_REGISTERS,
+        };
+        self.transaction = self.transaction.wrapping_add(1);
+
+        // [MBAP: TX u16][PROTOCOL u16 = 0][LEN u16][UNIT u8] [PDU: FN u8][START u16][COUNT u16]
+        let mut req = Vec::with_capacity(12);
+        req.extend_from_slice(&self.transaction.to_be_bytes());
+        req.extend_from_slice(&0u16.to_be_bytes());
+        req.extend_from_slice(&6u16.to_be_bytes());
+        req.push(self.unit_id);
+        req.push(function);
+        req.extend_from_slice(&start.to_be_bytes());
+        req.extend_from_slice(&count.to_be_bytes());
+        self.stream.write_all(&req).await?;
+
+        let mut header = [0u8; 7];
+        self.stream.read_exact(&mut header).await?;
+        let tx = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if tx != self.transaction {
+            return Err(ModbusError::Protocol(format!(
+                "transaction {tx}, expected {}",
+                self.transaction
+            )));
+        }
+        // the unit id is part of the length
+        if len < 2 {
+            return Err(ModbusError::Protocol(format!("length {len}")));
+        }
+
+        let mut pdu = vec![0u8; len - 1];
+        self.stream.read_exact(&mut pdu).await?;
+
+        if pdu[0] == function | 0x80 {
+            return Err(ModbusError::Exception(pdu.get(1).copied().unwrap_or(0)));
+        }
+        if pdu[0] != function {
+            return Err(ModbusError::Protocol(format!("function {:#04x}", pdu[0])));
+        }
+
+        let bytes = pdu.get(2..).unwrap_or_default();
+        if pdu.get(1).copied() != Some(count as u8 * 2) || bytes.len() != count as usize * 2 {
+            return Err(ModbusError::Protocol(format!(
+                "{} register bytes, expected {}",
+                bytes.len(),
+                count * 2
+            )));
+        }
+
+        Ok(bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // serves one canned response per request, `respond` gets the request frame
+    async fn client_for(
+        respond: impl Fn(&[u8]) -> Vec<u8> + Send + 'static,
+    ) -> (ModbusClient, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut req = [0u8; 12];
+            while stream.read_exact(&mut req).await.is_ok() {
+                stream.write_all(&respond(&req)).await.unwrap();
+            }
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let client = ModbusClient::new(stream, 1, Duration::from_millis(500));
+        (client, server)
+    }
+
+    // MBAP header of a response to `req` followed by the pdu
+    fn frame(req: &[u8], pdu: &[u8]) -> Vec<u8> {
+        let mut res = req[0..4].to_vec();
+        res.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        res.push(req[6]);
+        res.extend_from_slice(pdu);
+        res
+    }
+
+    #[tokio::test]
+    async fn reads_registers() {
+        let (mut client, _server) = client_for(|req| {
+            // function, start 0x0010, count 2
+            assert_eq!(&req[7..12], &[READ_INPUT_REGISTERS, 0x00, 0x10, 0x00, 0x02]);
+            frame(req, &[READ_INPUT_REGISTERS, 4, 0x41, 0xac, 0x00, 0x00])
+        })
+        .await;
+
+        for _ in 0..2 {
+            let regs = client
+                .read_registers(RegisterKind::Input, 0x10, 2)
+                .await
+                .unwrap();
+            assert_eq!(regs, [0x41ac, 0x0000]);
+        }
+    }
+
+    #[tokio::test]
+    async fn exception_response() {
+        let (mut client, _server) =
+            client_for(|req| frame(req, &[READ_HOLDING_REGISTERS | 0x80, 0x02])).await;
+
+        let err = client.read_registers(RegisterKind::Holding, 0, 1).await;
+        assert!(matches!(err, Err(ModbusError::Exception(0x02))));
+        assert_eq!(err.unwrap_err().quality(), Quality::BAD_DEVICE_FAILURE);
+    }
+
+    #[tokio::test]
+    async fn short_pdu() {
+        let (mut client, _server) =
+            client_for(|req| frame(req, &[READ_HOLDING_REGISTERS, 4, 0x00, 0x01])).await;
+
+        let err = client.read_registers(RegisterKind::Holding, 0, 2).await;
+        assert!(matches!(err, Err(ModbusError::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn length_without_pdu() {
+        let (mut client, _server) = client_for(|req| frame(req, &[])).await;
+
+        let err = client.read_registers(RegisterKind::Holding, 0, 1).await;
+        assert!(matches!(err, Err(ModbusError::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn transaction_mismatch() {
+        let (mut client, _server) = client_for(|req| {
+            let mut res = frame(req, &[READ_HOLDING_REGISTERS, 2, 0x00, 0x01]);
+            res[1] = res[1].wrapping_add(1);
+            res
+        })
+        .await;
+
+        let err = client.read_registers(RegisterKind::Holding, 0, 1).await;
+        assert!(matches!(err, Err(ModbusError::Protocol(_))));
+        assert_eq!(err.unwrap_err().quality(), Quality::BAD_COMM_FAILURE);
+    }
+
+    #[tokio::test]
+    async fn unexpected_function() {
+        let (mut client, _server) =
+            client_for(|req| frame(req, &[READ_INPUT_REGISTERS, 2, 0x00, 0x01])).await;
+
+        let err = client.read_registers(RegisterKind::Holding, 0, 1).await;
+        assert!(matches!(err, Err(ModbusError::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let (mut client, _server) = client_for(|_| vec![]).await;
+
+        let err = client.read_registers(RegisterKind::Holding, 0, 1).await;
+        assert!(matches!(err, Err(ModbusError::Timeout)));
+    }
+}
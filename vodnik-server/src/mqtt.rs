@@ -0,0 +1,386 @@
+// MQTT subscriber ingest, enabled with the `mqtt` feature and configured via the JSON file in
+// VODNIK_MQTT_CONFIG. messages are mapped to series by topic and written through the regular
+// ingest path (WAL + HotSet).
+//
+// local run against a mosquitto broker:
+//   docker run --rm -p 1883:1883 eclipse-mosquitto:2 mosquitto -c /mosquitto-no-auth.conf
+//   VODNIK_MQTT_CONFIG=mqtt.json cargo run -p vodnik-server --features mqtt
+//   mosquitto_pub -t factory/line1/temperature -m '{"value": 21.5}'
+// with mqtt.json:
+//   {"subscriptions": [{"filter": "factory/#"}],
+//    "mappings": [{"topic": "factory/line1/temperature", "series": 14}]}
+// decoding is covered by `cargo test -p vodnik-server --features mqtt`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, Publish, QoS};
+use serde::Deserialize;
+use serde_json::Number;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+use vodnik_core::{
+    api::{BatchIngest, ValueVec},
+    meta::{Quality, SeriesId, SeriesMeta},
+};
+
+use crate::{
+    AppState,
+    api::ApiError,
+    ingest::{stream::push_value, write_batch},
+    meta::into_api_error,
+};
+
+mod sparkplug;
+
+// requests queued in the client, drained by the event loop
+const CLIENT_CAPACITY: usize = 64;
+// publishes waiting for the bridge. the event loop only blocks once these are used up, until
+// then it keeps answering pings while samples are written
+const PENDING_PUBLISHES: usize = 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct MqttConfig {
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive_secs: u64,
+    pub subscriptions: Vec<Subscription>,
+    pub mappings: Vec<TopicMapping>,
+}
+
+fn default_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "vodnik".to_string()
+}
+
+fn default_keep_alive() -> u64 {
+    30
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Subscription {
+    // topic filter, e.g. factory/+/temperature or spBv1.0/plant1/#
+    pub filter: String,
+    #[serde(default)]
+    pub format: PayloadFormat,
+    #[serde(default)]
+    pub qos: u8,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    // {"ts": 1767111429344, "value": 12.5, "q": 192} or an array of those. ts defaults to the
+    // receive time, q to good.
+    #[default]
+    Json,
+    // Sparkplug B protobuf payloads, each metric is mapped on its own as
+    // spBv1.0/<group>/<edge node>[/<device>]/<metric name>
+    SparkplugB,
+}
+
+// maps a topic (or sparkplug metric path) to a series. the topic may contain wildcards.
+#[derive(Debug, Deserialize)]
+pub struct TopicMapping {
+    pub topic: String,
+    #[serde(flatten)]
+    pub target: SeriesRef,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SeriesRef {
+    Id { series: SeriesId },
+    Name { series_name: String },
+}
+
+impl MqttConfig {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let cfg: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        for sub in &cfg.subscriptions {
+            if !rumqttc::valid_filter(&sub.filter) {
+                anyhow::bail!("invalid topic filter '{}'", sub.filter);
+            }
+            rumqttc::qos(sub.qos).map_err(|e| anyhow::anyhow!("{}: {e:?}", sub.filter))?;
+        }
+        Ok(cfg)
+    }
+}
+
+// a decoded sample, `key` is the topic or sparkplug metric path it is mapped by
+struct Sample {
+    key: String,
+    ts: u64,
+    value: Number,
+    q: Quality,
+}
+
+struct Bridge {
+    state: AppState,
+    config: MqttConfig,
+    // mapped key -> series, resolved on first use
+    resolved: HashMap<String, SeriesMeta>,
+    aliases: sparkplug::Aliases,
+}
+
+pub fn spawn(config: MqttConfig, state: AppState) {
+    let mut opts = MqttOptions::new(&config.client_id, &config.host, config.port);
+    opts.set_keep_alive(Duration::from_secs(config.keep_alive_secs));
+    if let (Some(user), Some(pass)) = (&config.username, &config.password) {
+        opts.set_credentials(user, pass);
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(opts, CLIENT_CAPACITY);
+    let filters: Vec<(String, QoS)> = config
+        .subscriptions
+        .iter()
+        .map(|s| {
+            let qos = rumqttc::qos(s.qos).unwrap_or(QoS::AtMostOnce);
+            (s.filter.clone(), qos)
+        })
+        .collect();
+    info!("mqtt: connecting to {}:{}", config.host, config.port);
+
+    let (publishes, mut pending) = mpsc::channel::<Publish>(PENDING_PUBLISHES);
+    let mut bridge = Bridge {
+        state,
+        config,
+        resolved: HashMap::new(),
+        aliases: sparkplug::Aliases::default(),
+    };
+
+    tokio::spawn(async move {
+        while let Some(msg) = pending.recv().await {
+            if let Err(e) = bridge.handle(&msg).await {
+                warn!("mqtt: message on {} dropped: {e}", msg.topic);
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    // subscriptions don't survive a clean session reconnect. the requests only
+                    // leave the client while this loop polls, so they are sent from another task
+                    tokio::spawn(subscribe(client.clone(), filters.clone()));
+                }
+                Ok(Event::Incoming(Packet::Publish(msg))) => {
+                    if publishes.send(msg).await.is_err() {
+                        error!("mqtt: bridge stopped, disconnecting");
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("mqtt: connection error: {e}, reconnecting");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+}
+
+async fn subscribe(client: AsyncClient, filters: Vec<(String, QoS)>) {
+    for (filter, qos) in &filters {
+        if let Err(e) = client.subscribe(filter, *qos).await {
+            error!("mqtt: subscribe to {filter} failed: {e}");
+        }
+    }
+    info!("mqtt: connected, {} subscriptions", filters.len());
+}
+
+impl Bridge {
+    async fn handle(&mut self, msg: &Publish) -> Result<(), ApiError> {
+        let Some(sub) = self
+            .config
+            .subscriptions
+            .iter()
+            .find(|s| rumqttc::matches(&msg.topic, &s.filter))
+        else {
+            return Ok(());
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let samples = match sub.format {
+            PayloadFormat::Json => decode_json(&msg.topic, &msg.payload, now)?,
+            PayloadFormat::SparkplugB => {
+                sparkplug::decode(&mut self.aliases, &msg.topic, &msg.payload, now)?
+            }
+        };
+
+        let mut by_series: BTreeMap<SeriesId, (SeriesMeta, Vec<Sample>)> = BTreeMap::new();
+        for sample in samples {
+            let Some(series) = self.resolve(&sample.key).await? else {
+                debug!("mqtt: no series mapped for {}", sample.key);
+                continue;
+            };
+            by_series
+                .entry(series.id)
+                .or_insert_with(|| (series, vec![]))
+                .1
+                .push(sample);
+        }
+
+        for (id, (series, mut samples)) in by_series {
+            samples.sort_by_key(|s| s.ts);
+
+            let mut vals = ValueVec::with_type(series.storage_type);
+            for s in &samples {
+                push_value(&mut vals, &s.value).map_err(|e| {
+                    ApiError::BadRequest(format!("series '{}' ({id}): {e}", series.name))
+                })?;
+            }
+            let batch = BatchIngest {
+                series: id,
+                ts: samples.iter().map(|s| s.ts).collect(),
+                qs: samples.iter().map(|s| s.q).collect(),
+                batch_id: None,
+                vals,
+            };
+            batch.validate()?;
+
+            if let Err(e) = write_batch(&self.state, &series, batch, None).await {
+                // the series might be gone, resolve it again next time
+                self.resolved.retain(|_, s| s.id != id);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn resolve(&mut self, key: &str) -> Result<Option<SeriesMeta>, ApiError> {
+        if let Some(series) = self.resolved.get(key) {
+            return Ok(Some(series.clone()));
+        }
+        let Some(mapping) = self
+            .config
+            .mappings
+            .iter()
+            .find(|m| rumqttc::matches(key, &m.topic))
+        else {
+            return Ok(None);
+        };
+
+        let series = match &mapping.target {
+            SeriesRef::Id { series } => self
+                .state
+                .meta_store
+                .get(*series)
+                .await
+                .map_err(into_api_error)?,
+            SeriesRef::Name { series_name } => {
+                let mut found = self
+                    .state
+                    .meta_store
+                    .find_by_name(series_name)
+                    .await
+                    .map_err(into_api_error)?;
+                if found.len() != 1 {
+                    return Err(ApiError::NotFound(format!(
+                        "{} series named '{series_name}', expected one",
+                        found.len()
+                    )));
+                }
+                found.remove(0)
+            }
+        };
+
+        self.resolved.insert(key.to_string(), series.clone());
+        Ok(Some(series))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonPayload {
+    One(JsonSample),
+    Many(Vec<JsonSample>),
+}
+
+#[derive(Deserialize)]
+struct JsonSample {
+    ts: Option<u64>,
+    value: Number,
+    #[serde(default)]
+    q: Quality,
+}
+
+fn decode_json(topic: &str, payload: &[u8], now: u64) -> Result<Vec<Sample>, ApiError> {
+    let samples = match serde_json::from_slice(payload)
+        .map_err(|e| ApiError::BadRequest(format!("invalid json payload: {e}")))?
+    {
+        JsonPayload::One(s) => vec![s],
+        JsonPayload::Many(s) => s,
+    };
+
+    Ok(samples
+        .into_iter()
+        .map(|s| Sample {
+            key: topic.to_string(),
+            ts: s.ts.unwrap_or(now),
+            value: s.value,
+            q: s.q,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_single_sample() {
+        let payload = br#"{"ts": 1767111429344, "value": 12.5, "q": 0}"#;
+        let samples = decode_json("factory/line1/temperature", payload, 42).unwrap();
+
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].key, "factory/line1/temperature");
+        assert_eq!(samples[0].ts, 1767111429344);
+        assert_eq!(samples[0].value.as_f64(), Some(12.5));
+        assert_eq!(samples[0].q, Quality(0));
+    }
+
+    #[test]
+    fn json_array_defaults_ts_and_q() {
+        let payload = br#"[{"value": 1}, {"ts": 1000, "value": -2}]"#;
+        let samples = decode_json("t", payload, 42).unwrap();
+
+        assert_eq!(samples.len(), 2);
+        assert_eq!((samples[0].ts, samples[0].q), (42, Quality::GOOD));
+        assert_eq!(samples[0].value.as_u64(), Some(1));
+        assert_eq!(samples[1].ts, 1000);
+        assert_eq!(samples[1].value.as_i64(), Some(-2));
+    }
+
+    #[test]
+    fn json_invalid_payload() {
+        assert!(matches!(
+            decode_json("t", b"21.5", 42),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(matches!(
+            decode_json("t", br#"{"ts": 1}"#, 42),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
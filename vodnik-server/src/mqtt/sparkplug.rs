@@ -0,0 +1,323 @@
+// Sparkplug B payload decoding. only the parts of the schema needed for numeric metrics are
+// declared, prost skips all other fields.
+//
+// topics: spBv1.0/<group>/<NBIRTH|NDATA|DBIRTH|DDATA>/<edge node>[/<device>]
+
+use std::collections::HashMap;
+
+use serde_json::Number;
+use vodnik_core::meta::Quality;
+
+use super::Sample;
+use crate::api::ApiError;
+
+const NAMESPACE: &str = "spBv1.0";
+// metric property holding the quality code
+const QUALITY_PROPERTY: &str = "Quality";
+
+// sparkplug data types of signed integers, which are sent as two's complement in the unsigned
+// int_value / long_value
+const INT8: u32 = 1;
+const INT16: u32 = 2;
+const INT32: u32 = 3;
+const INT64: u32 = 4;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Payload {
+    #[prost(uint64, optional, tag = "1")]
+    pub timestamp: Option<u64>,
+    #[prost(message, repeated, tag = "2")]
+    pub metrics: Vec<Metric>,
+    #[prost(uint64, optional, tag = "3")]
+    pub seq: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Metric {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(uint64, optional, tag = "2")]
+    pub alias: Option<u64>,
+    #[prost(uint64, optional, tag = "3")]
+    pub timestamp: Option<u64>,
+    #[prost(uint32, optional, tag = "4")]
+    pub datatype: Option<u32>,
+    #[prost(bool, optional, tag = "7")]
+    pub is_null: Option<bool>,
+    #[prost(message, optional, tag = "9")]
+    pub properties: Option<PropertySet>,
+    #[prost(oneof = "MetricValue", tags = "10, 11, 12, 13, 14, 15")]
+    pub value: Option<MetricValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum MetricValue {
+    #[prost(uint32, tag = "10")]
+    Int(u32),
+    #[prost(uint64, tag = "11")]
+    Long(u64),
+    #[prost(float, tag = "12")]
+    Float(f32),
+    #[prost(double, tag = "13")]
+    Double(f64),
+    #[prost(bool, tag = "14")]
+    Boolean(bool),
+    #[prost(string, tag = "15")]
+    String(String),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PropertySet {
+    #[prost(string, repeated, tag = "1")]
+    pub keys: Vec<String>,
+    #[prost(message, repeated, tag = "2")]
+    pub values: Vec<PropertyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PropertyValue {
+    #[prost(uint32, optional, tag = "1")]
+    pub r#type: Option<u32>,
+    #[prost(uint32, optional, tag = "3")]
+    pub int_value: Option<u32>,
+    #[prost(uint64, optional, tag = "4")]
+    pub long_value: Option<u64>,
+}
+
+// metric aliases per (group, edge node), announced in the birth certificates
+#[derive(Debug, Default)]
+pub struct Aliases(HashMap<(String, String), HashMap<u64, String>>);
+
+pub fn decode(
+    aliases: &mut Aliases,
+    topic: &str,
+    payload: &[u8],
+    now: u64,
+) -> Result<Vec<super::Sample>, ApiError> {
+    let parts: Vec<&str> = topic.splitn(5, '/').collect();
+    let (group, kind, edge, device) = match parts.as_slice() {
+        [NAMESPACE, group, kind, edge] => (*group, *kind, *edge, None),
+        [NAMESPACE, group, kind, edge, device] => (*group, *kind, *edge, Some(*device)),
+        _ => return Ok(vec![]),
+    };
+    let birth = match kind {
+        "NBIRTH" | "DBIRTH" => true,
+        "NDATA" | "DDATA" => false,
+        // death certificates, commands, STATE
+        _ => return Ok(vec![]),
+    };
+
+    let payload = <Payload as prost::Message>::decode(payload)
+        .map_err(|e| ApiError::BadRequest(format!("invalid sparkplug payload: {e}")))?;
+
+    let node = (group.to_string(), edge.to_string());
+    if kind == "NBIRTH" {
+        // a rebirth invalidates all aliases of the node
+        aliases.0.remove(&node);
+    }
+    let node_aliases = aliases.0.entry(node).or_default();
+
+    let prefix = match device {
+        Some(device) => format!("{NAMESPACE}/{group}/{edge}/{device}"),
+        None => format!("{NAMESPACE}/{group}/{edge}"),
+    };
+
+    let mut samples = vec![];
+    for metric in payload.metrics {
+        if birth && let (Some(name), Some(alias)) = (&metric.name, metric.alias) {
+            node_aliases.insert(alias, name.clone());
+        }
+
+        let name = match (&metric.name, metric.alias) {
+            (Some(name), _) => name.clone(),
+            (None, Some(alias)) => match node_aliases.get(&alias) {
+                Some(name) => name.clone(),
+                None => continue,
+            },
+            (None, None) => continue,
+        };
+        if metric.is_null == Some(true) {
+            continue;
+        }
+        let Some(value) = metric_value(&metric) else {
+            continue;
+        };
+
+        samples.push(Sample {
+            key: format!("{prefix}/{name}"),
+            ts: metric.timestamp.or(payload.timestamp).unwrap_or(now),
+            value,
+            q: metric_quality(&metric),
+        });
+    }
+
+    Ok(samples)
+}
+
+// numeric value of a metric, strings and other types are skipped
+fn metric_value(metric: &Metric) -> Option<Number> {
+    let signed = matches!(metric.datatype, Some(INT8 | INT16 | INT32 | INT64));
+    match metric.value.as_ref()? {
+        MetricValue::Int(v) if signed => Some((*v as i32).into()),
+        MetricValue::Int(v) => Some((*v).into()),
+        MetricValue::Long(v) if signed => Some((*v as i64).into()),
+        MetricValue::Long(v) => Some((*v).into()),
+        MetricValue::Float(v) => Number::from_f64(*v as f64),
+        MetricValue::Double(v) => Number::from_f64(*v),
+        MetricValue::Boolean(v) => Some((*v as u8).into()),
+        MetricValue::String(_) => None,
+    }
+}
+
+// quality from the metric properties, good if not set. codes outside the OPC byte are bad.
+fn metric_quality(metric: &Metric) -> Quality {
+    let Some(props) = &metric.properties else {
+        return Quality::GOOD;
+    };
+    let Some(value) = props
+        .keys
+        .iter()
+        .position(|k| k == QUALITY_PROPERTY)
+        .and_then(|i| props.values.get(i))
+    else {
+        return Quality::GOOD;
+    };
+
+    let code = value
+        .int_value
+        .map(u64::from)
+        .or(value.long_value)
+        .unwrap_or(Quality::GOOD.0 as u64);
+    u8::try_from(code).map_or(Quality::BAD, Quality)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    fn metric(name: Option<&str>, alias: Option<u64>, value: MetricValue) -> Metric {
+        Metric {
+            name: name.map(str::to_string),
+            alias,
+            value: Some(value),
+            ..Default::default()
+        }
+    }
+
+    fn payload(timestamp: u64, metrics: Vec<Metric>) -> Vec<u8> {
+        Payload {
+            timestamp: Some(timestamp),
+            metrics,
+            seq: Some(0),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn data_resolves_aliases_from_birth() {
+        let mut aliases = Aliases::default();
+        let birth = payload(
+            1000,
+            vec![metric(
+                Some("temperature"),
+                Some(7),
+                MetricValue::Double(20.0),
+            )],
+        );
+        let samples = decode(&mut aliases, "spBv1.0/plant1/NBIRTH/edge1", &birth, 0).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].key, "spBv1.0/plant1/edge1/temperature");
+        assert_eq!(samples[0].ts, 1000);
+
+        let data = payload(2000, vec![metric(None, Some(7), MetricValue::Double(21.5))]);
+        let samples = decode(&mut aliases, "spBv1.0/plant1/NDATA/edge1", &data, 0).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].key, "spBv1.0/plant1/edge1/temperature");
+        assert_eq!(samples[0].ts, 2000);
+        assert_eq!(samples[0].value.as_f64(), Some(21.5));
+        assert_eq!(samples[0].q, Quality::GOOD);
+
+        // aliases belong to the edge node, not to other nodes of the group
+        let samples = decode(&mut aliases, "spBv1.0/plant1/NDATA/edge2", &data, 0).unwrap();
+        assert!(samples.is_empty());
+    }
+
+    #[test]
+    fn rebirth_drops_aliases() {
+        let mut aliases = Aliases::default();
+        let birth = payload(1000, vec![metric(Some("a"), Some(1), MetricValue::Int(1))]);
+        decode(&mut aliases, "spBv1.0/g/NBIRTH/e", &birth, 0).unwrap();
+
+        let rebirth = payload(1000, vec![metric(Some("b"), Some(2), MetricValue::Int(1))]);
+        decode(&mut aliases, "spBv1.0/g/NBIRTH/e", &rebirth, 0).unwrap();
+
+        let data = payload(
+            2000,
+            vec![
+                metric(None, Some(1), MetricValue::Int(5)),
+                metric(None, Some(2), MetricValue::Int(6)),
+            ],
+        );
+        let samples = decode(&mut aliases, "spBv1.0/g/NDATA/e", &data, 0).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].key, "spBv1.0/g/e/b");
+    }
+
+    #[test]
+    fn device_metrics_and_values() {
+        let mut aliases = Aliases::default();
+        let mut signed = metric(Some("offset"), None, MetricValue::Int(-3i32 as u32));
+        signed.datatype = Some(INT32);
+        signed.timestamp = Some(1500);
+        let mut null = metric(Some("gone"), None, MetricValue::Int(0));
+        null.is_null = Some(true);
+        let mut bad = metric(Some("pressure"), None, MetricValue::Float(1.5));
+        bad.properties = Some(PropertySet {
+            keys: vec![QUALITY_PROPERTY.to_string()],
+            values: vec![PropertyValue {
+                r#type: Some(3),
+                int_value: Some(0),
+                long_value: None,
+            }],
+        });
+
+        let data = payload(
+            2000,
+            vec![
+                signed,
+                null,
+                bad,
+                metric(Some("running"), None, MetricValue::Boolean(true)),
+                metric(Some("label"), None, MetricValue::String("x".to_string())),
+            ],
+        );
+        let samples = decode(&mut aliases, "spBv1.0/g/DDATA/e/pump", &data, 0).unwrap();
+
+        let keys: Vec<&str> = samples.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "spBv1.0/g/e/pump/offset",
+                "spBv1.0/g/e/pump/pressure",
+                "spBv1.0/g/e/pump/running"
+            ]
+        );
+        assert_eq!(samples[0].value.as_i64(), Some(-3));
+        assert_eq!(samples[0].ts, 1500);
+        assert_eq!(samples[1].q, Quality(0));
+        assert_eq!(samples[2].value.as_u64(), Some(1));
+    }
+
+    #[test]
+    fn ignores_other_topics() {
+        let mut aliases = Aliases::default();
+        let data = payload(0, vec![metric(Some("a"), None, MetricValue::Int(1))]);
+
+        for topic in ["spBv1.0/g/NDEATH/e", "spBv1.0/STATE/host", "factory/line1"] {
+            assert!(decode(&mut aliases, topic, &data, 0).unwrap().is_empty());
+        }
+        assert!(decode(&mut aliases, "spBv1.0/g/NDATA/e", b"\xff\xff", 0).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::BTreeMap, num::NonZero};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    response::AppendHeaders,
+};
+use serde::Deserialize;
+use tracing::info;
+use ulid::Ulid;
+use vodnik_core::{
+    api::{OPERATION_HEADER, RevertBlock, RevertResult},
+    helpers,
+    meta::{BlockNumber, SeriesId, SizedBlock},
+    wal::TxId,
+};
+
+use crate::{AppState, api::ApiError, meta::into_api_error, persistence, wal::next_txid};
+
+// response of a write request together with the header naming its operation, if there is one
+pub(crate) type WithOperation<T> = (AppendHeaders<Option<(&'static str, String)>>, T);
+
+// every write request gets its own operation id. it is recorded on every block version holding
+// samples of the request, blocks held in memory keep it until they are flushed. samples recovered
+// from the WAL after a crash are flushed without one.
+pub(crate) fn new_operation() -> String {
+    Ulid::new().to_string()
+}
+
+pub(crate) fn with_operation<T>(op: impl Into<Option<String>>, res: T) -> WithOperation<T> {
+    (
+        AppendHeaders(op.into().map(|op| (OPERATION_HEADER, op))),
+        res,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevertQuery {
+    // only list the blocks a revert would touch
+    #[serde(default)]
+    pub dry_run: bool,
+    // revert blocks that were written again after the operation as well
+    #[serde(default)]
+    pub force: bool,
+}
+
+// points every block written by the operation back to the version it had before. the reverted
+// versions stay in storage, the revert itself is recorded as a new operation.
+pub(crate) async fn revert_operation(
+    State(state): State<AppState>,
+    Path(op): Path<String>,
+    Query(query): Query<RevertQuery>,
+) -> Result<WithOperation<Json<RevertResult>>, ApiError> {
+    let blocks = plan_revert(&state, &op).await?;
+
+    if !query.dry_run {
+        let hot = blocks
+            .iter()
+            .find(|b| b.hot)
+            .map(|b| (b.series, b.block))
+            .or_else(|| state.hot.blocks_of_operation(&op).first().copied());
+        if let Some((series, block)) = hot {
+            return Err(ApiError::Conflict(format!(
+                "block {} of series {series} is held in memory, retry once it is flushed",
+                block.0
+            )));
+        }
+        if !query.force
+            && let Some(b) = blocks.iter().find(|b| b.later_versions > 0)
+        {
+            return Err(ApiError::Conflict(format!(
+                "block {} of series {} was written again after operation {op}, use force=true to revert those writes too",
+                b.block.0, b.series
+            )));
+        }
+        if !query.force
+            && let Some(b) = blocks.iter().find(|b| !b.other_operations.is_empty())
+        {
+            return Err(ApiError::Conflict(format!(
+                "block {} of series {} holds samples of other operations ({}), use force=true to revert those writes too",
+                b.block.0,
+                b.series,
+                b.other_operations.join(", ")
+            )));
+        }
+    }
+
+    let revert_op = new_operation();
+    if !query.dry_run {
+        for b in &blocks {
+            revert_block(&state, b, &revert_op).await?;
+        }
+        info!(
+            "reverted operation {op} in {} blocks as operation {revert_op}",
+            blocks.len()
+        );
+    }
+
+    Ok(with_operation(
+        revert_op,
+        Json(RevertResult {
+            dry_run: query.dry_run,
+            blocks,
+        }),
+    ))
+}
+
+async fn plan_revert(state: &AppState, op: &str) -> Result<Vec<RevertBlock>, ApiError> {
+    let versions = state.block_meta.versions_of_operation(op).await?;
+    if versions.is_empty() {
+        if let Some((series, block)) = state.hot.blocks_of_operation(op).first() {
+            return Err(ApiError::Conflict(format!(
+                "operation {op} is only held in memory (block {} of series {series}), retry once it is flushed",
+                block.0
+            )));
+        }
+        return Err(ApiError::NotFound(format!(
+            "no block versions of operation {op}"
+        )));
+    }
+
+    // first and last version of each block, an overwrite may write a block more than once
+    let mut written: BTreeMap<(i64, i64), (i64, i64, String)> = BTreeMap::new();
+    for v in versions {
+        written
+            .entry((v.series_id, v.block_id))
+            .and_modify(|(_, last, key)| {
+                *last = v.id;
+                key.clone_from(&v.object_key);
+            })
+            .or_insert((v.id, v.id, v.object_key));
+    }
+
+    let mut blocks = Vec::with_capacity(written.len());
+    for ((series_id, block_id), (first, last, object_key)) in written {
+        let series = SeriesId(NonZero::new(series_id as u64).unwrap());
+        let block = BlockNumber(block_id as u64);
+
+        let previous = state
+            .block_meta
+            .version_before(series, block, first)
+            .await?;
+
+        // requests that wrote into the block since, except the ones already contained in the
+        // version the block goes back to
+        let mut others = state
+            .block_meta
+            .operations_since(series, block, first)
+            .await?;
+        others.remove(op);
+        if let Some(previous) = &previous {
+            for kept in state.block_meta.operations_of_version(previous.id).await? {
+                others.remove(&kept);
+            }
+        }
+
+        let (live, flushing) = state.hot.get_live_blocks(series);
+        blocks.push(RevertBlock {
+            series,
+            block,
+            object_key,
+            previous_object_key: previous.map(|v| v.object_key),
+            later_versions: state
+                .block_meta
+                .count_versions_after(series, block, last)
+                .await?,
+            other_operations: others.into_iter().collect(),
+            hot: live == Some(block) || flushing.contains(&block),
+        });
+    }
+
+    Ok(blocks)
+}
+
+async fn revert_block(state: &AppState, b: &RevertBlock, op: &str) -> Result<(), ApiError> {
+    let tx = TxId(next_txid());
+    let _guard = state.block_locks.lock(b.series, b.block).await;
+
+    match &b.previous_object_key {
+        Some(key) => {
+            // the block meta is recomputed from the samples of the old version
+            let mut previous = persistence::read_block_version(&state.storage, key.clone()).await?;
+            previous.recalc_meta();
+            persistence::point_block(
+                &state.block_meta,
+                b.series,
+                b.block,
+                key,
+                &previous,
+                tx,
+                &[op],
+            )
+            .await
+        }
+        None => {
+            let series = state
+                .meta_store
+                .get(b.series)
+                .await
+                .map_err(into_api_error)?;
+            let len = helpers::get_block_length(&series) as usize;
+            let empty = SizedBlock::empty(series.storage_type, len);
+            persistence::flush_block(
+                &state.storage,
+                &state.block_meta,
+                b.series,
+                b.block,
+                &empty,
+                tx,
+                &[op],
+            )
+            .await
+            .map(|_| ())
+        }
+    }
+}
@@ -1,18 +1,64 @@
 use crate::api::ApiError;
 use crate::meta::block::BlockMetaStore;
+use dashmap::DashMap;
 use opendal::Operator;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
 use tracing::{debug, error};
 use ulid::Ulid;
+use vodnik_core::aggregate::integral::BlockIntegral;
 use vodnik_core::helpers;
-use vodnik_core::meta::{BlockNumber, BlockWritable, SeriesId, SizedBlock, WriteBatch};
+use vodnik_core::meta::{BlockNumber, BlockWritable, SeriesId, SizedBlock, WriteBatch, WriteStats};
+use vodnik_core::wal::TxId;
 
+// serializes read-modify-flush cycles of stored blocks, e.g. a backfill and a delete of the
+// same block. without it the flush that lands last drops the changes of the other one.
+// flushes of hot blocks hold it as well, changes of a hot block wait for its flush to finish.
+#[derive(Clone, Debug, Default)]
+pub struct BlockLocks(Arc<LockMap>);
+
+type LockMap = DashMap<(SeriesId, BlockNumber), Arc<Mutex<()>>>;
+
+pub struct BlockGuard {
+    locks: BlockLocks,
+    key: (SeriesId, BlockNumber),
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl BlockLocks {
+    pub async fn lock(&self, series_id: SeriesId, block_id: BlockNumber) -> BlockGuard {
+        let key = (series_id, block_id);
+        let mutex = self.0.entry(key).or_default().clone();
+        BlockGuard {
+            locks: self.clone(),
+            key,
+            guard: Some(mutex.lock_owned().await),
+        }
+    }
+}
+
+impl Drop for BlockGuard {
+    fn drop(&mut self) {
+        self.guard = None;
+        // only the map holds the mutex now, nobody is waiting for it
+        self.locks
+            .0
+            .remove_if(&self.key, |_, m| Arc::strong_count(m) == 1);
+    }
+}
+
+// writes the block as a new object, points the block meta to it and records it as a new version
+// of the block. `tx` is the largest tx contained in the block, `operations` the requests that
+// wrote into it. returns the object key.
 pub async fn flush_block(
     op: &Operator,
     db: &BlockMetaStore,
     series_id: SeriesId,
     block_id: BlockNumber,
     block: &SizedBlock,
-) -> Result<(), ApiError> {
+    tx: TxId,
+    operations: &[&str],
+) -> Result<String, ApiError> {
     // Format: data/{series_id % 100}/{series_id}/{block_id}_{uuid}.blk
     let path_pref = series_id.0.get() % 100u64;
     let write_id = Ulid::new();
@@ -31,18 +77,56 @@ pub async fn flush_block(
         ApiError::Internal
     })?;
 
-    // update metadata
+    point_block(db, series_id, block_id, &object_key, block, tx, operations).await?;
+    Ok(object_key)
+}
+
+// points the block meta to an already stored object and records it as a new version of the block
+pub async fn point_block(
+    db: &BlockMetaStore,
+    series_id: SeriesId,
+    block_id: BlockNumber,
+    object_key: &str,
+    block: &SizedBlock,
+    tx: TxId,
+    operations: &[&str],
+) -> Result<(), ApiError> {
+    let key = object_key.to_string();
+    let integral = BlockIntegral::from_block(block);
     let result = match block {
-        SizedBlock::F32Block(meta, ..) => db.upsert(series_id, block_id, object_key, meta).await,
-        SizedBlock::F64Block(meta, ..) => db.upsert(series_id, block_id, object_key, meta).await,
-        SizedBlock::I32Block(meta, ..) => db.upsert(series_id, block_id, object_key, meta).await,
-        SizedBlock::I64Block(meta, ..) => db.upsert(series_id, block_id, object_key, meta).await,
-        SizedBlock::U32Block(meta, ..) => db.upsert(series_id, block_id, object_key, meta).await,
-        SizedBlock::U64Block(meta, ..) => db.upsert(series_id, block_id, object_key, meta).await,
-        SizedBlock::U8Block(meta, ..) => db.upsert(series_id, block_id, object_key, meta).await,
+        SizedBlock::F32Block(meta, ..) => {
+            db.upsert(series_id, block_id, key, meta, &integral).await
+        }
+        SizedBlock::F64Block(meta, ..) => {
+            db.upsert(series_id, block_id, key, meta, &integral).await
+        }
+        SizedBlock::I32Block(meta, ..) => {
+            db.upsert(series_id, block_id, key, meta, &integral).await
+        }
+        SizedBlock::I64Block(meta, ..) => {
+            db.upsert(series_id, block_id, key, meta, &integral).await
+        }
+        SizedBlock::U32Block(meta, ..) => {
+            db.upsert(series_id, block_id, key, meta, &integral).await
+        }
+        SizedBlock::U64Block(meta, ..) => {
+            db.upsert(series_id, block_id, key, meta, &integral).await
+        }
+        SizedBlock::U8Block(meta, ..) => db.upsert(series_id, block_id, key, meta, &integral).await,
     };
 
-    result.map_err(ApiError::from)
+    result.map_err(ApiError::from)?;
+
+    db.add_version(
+        series_id,
+        block_id,
+        object_key,
+        tx,
+        operations,
+        crate::ingest::now_ms(),
+    )
+    .await?;
+    Ok(())
 }
 
 pub async fn read_block_from_storage(
@@ -56,6 +140,11 @@ pub async fn read_block_from_storage(
         .await
         .map_err(ApiError::from)?;
 
+    read_block_version(op, key).await
+}
+
+// reads the block stored under `key`, which may be an older version of the block
+pub async fn read_block_version(op: &Operator, key: String) -> Result<SizedBlock, ApiError> {
     let bytes = op
         .read(&key)
         .await
@@ -83,8 +172,9 @@ pub async fn read_block_from_storage(
 pub(crate) async fn write_cold<'a, T: BlockWritable>(
     op: &Operator,
     db: &BlockMetaStore,
+    locks: &BlockLocks,
     batch: &'a WriteBatch<'a, T>,
-) -> Result<(), ApiError> {
+) -> Result<WriteStats, ApiError> {
     debug!(
         "write_bold:: Series={}, Block={:?}, #samples={}",
         batch.series.id,
@@ -92,6 +182,8 @@ pub(crate) async fn write_cold<'a, T: BlockWritable>(
         batch.ts.len()
     );
 
+    let _guard = locks.lock(batch.series.id, batch.block_id).await;
+
     let mut block_to_write =
         match read_block_from_storage(op, db, batch.series.id, batch.block_id).await {
             Ok(b) => b,
@@ -105,6 +197,16 @@ pub(crate) async fn write_cold<'a, T: BlockWritable>(
             }
         };
 
-    block_to_write.write(batch);
-    flush_block(op, db, batch.series.id, batch.block_id, &block_to_write).await
+    let stats = block_to_write.write(batch);
+    flush_block(
+        op,
+        db,
+        batch.series.id,
+        batch.block_id,
+        &block_to_write,
+        batch.tx,
+        batch.op.as_slice(),
+    )
+    .await?;
+    Ok(stats)
 }
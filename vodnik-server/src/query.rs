@@ -1,10 +1,23 @@
+use std::collections::BTreeMap;
+
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+
+use crate::{AppState, api::ApiError, crud::LabelSelector, meta::into_api_error, persistence};
+use vodnik_core::{
+    aggregate::integral::BlockIntegral,
+    api::SeriesData,
+    helpers,
+    meta::{BlockMeta, BlockNumber, BlockWritable, SeriesId, SeriesMeta, SizedBlock, StorageType},
 };
 
-use crate::{AppState, api::ApiError, persistence};
-use vodnik_core::meta::{BlockNumber, SeriesId, SizedBlock};
+pub mod aggregate;
+pub mod integral;
+pub mod opc;
+pub mod point;
 
 pub(crate) async fn read_single_block(
     State(state): State<AppState>,
@@ -20,3 +33,280 @@ pub(crate) async fn read_single_block(
 
     Ok(Json(b))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct RangeQuery {
+    // [from, to) in ms after UNIX EPOCH
+    pub from: u64,
+    pub to: u64,
+    // read the blocks as they were stored at this point in time (ms after UNIX EPOCH),
+    // samples only held in memory back then aren't included
+    #[serde(default)]
+    pub as_of: Option<u64>,
+}
+
+impl RangeQuery {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.from >= self.to {
+            return Err(ApiError::BadRequest(
+                "'from' must be strictly smaller than 'to'".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// the series a multi series query runs on, given by id and / or label selector
+#[derive(Debug, Deserialize)]
+pub struct SeriesTarget {
+    #[serde(default)]
+    pub series: Vec<SeriesId>,
+    pub selector: Option<LabelSelector>,
+}
+
+impl SeriesTarget {
+    // returns the targeted series sorted by id, without duplicates
+    pub(crate) async fn resolve(&self, state: &AppState) -> Result<Vec<SeriesMeta>, ApiError> {
+        if self.series.is_empty() && self.selector.is_none() {
+            return Err(ApiError::BadRequest("no series given".to_string()));
+        }
+
+        let mut res = BTreeMap::new();
+        for id in &self.series {
+            let series = state.meta_store.get(*id).await.map_err(into_api_error)?;
+            res.insert(series.id, series);
+        }
+        if let Some(selector) = &self.selector {
+            for series in selector.resolve(state).await? {
+                res.insert(series.id, series);
+            }
+        }
+
+        Ok(res.into_values().collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MultiRangeQuery {
+    #[serde(flatten)]
+    pub target: SeriesTarget,
+    // [from, to) in ms after UNIX EPOCH
+    pub from: u64,
+    pub to: u64,
+    // see `RangeQuery`
+    #[serde(default)]
+    pub as_of: Option<u64>,
+}
+
+pub(crate) async fn multi_read_range(
+    State(state): State<AppState>,
+    Json(query): Json<MultiRangeQuery>,
+) -> Result<Json<Vec<SeriesData>>, ApiError> {
+    let range = RangeQuery {
+        from: query.from,
+        to: query.to,
+        as_of: query.as_of,
+    };
+    range.validate()?;
+
+    let mut res = vec![];
+    for series in query.target.resolve(&state).await? {
+        res.push(series_samples(&state, &series, &range).await?);
+    }
+
+    Ok(Json(res))
+}
+
+pub(crate) async fn read_range(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Query(range): Query<RangeQuery>,
+) -> Result<Json<SeriesData>, ApiError> {
+    range.validate()?;
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+
+    Ok(Json(series_samples(&state, &series, &range).await?))
+}
+
+async fn series_samples(
+    state: &AppState,
+    series: &SeriesMeta,
+    range: &RangeQuery,
+) -> Result<SeriesData, ApiError> {
+    match series.storage_type {
+        StorageType::Float32 => read_samples::<f32>(state, series, range).await,
+        StorageType::Float64 => read_samples::<f64>(state, series, range).await,
+        StorageType::Int32 => read_samples::<i32>(state, series, range).await,
+        StorageType::Int64 => read_samples::<i64>(state, series, range).await,
+        StorageType::UInt32 => read_samples::<u32>(state, series, range).await,
+        StorageType::UInt64 => read_samples::<u64>(state, series, range).await,
+        StorageType::Enumeration => read_samples::<u8>(state, series, range).await,
+    }
+}
+
+async fn read_samples<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    range: &RangeQuery,
+) -> Result<SeriesData, ApiError> {
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+
+    let mut ts = vec![];
+    let mut vals = vec![];
+    let mut qs = vec![];
+
+    for block_ref in blocks_in_range::<T>(state, series, range.from, range.to, range.as_of).await? {
+        let Some(block) = load_block::<T>(state, series, &block_ref).await? else {
+            continue;
+        };
+
+        let bl_start = helpers::get_block_start_as_offset(series, block_ref.id.0);
+        let (_, block_vals, block_qs) = T::block_data(&block);
+
+        for i in 0..block_qs.len() {
+            let t = bl_start + i as u64 * sample_ms;
+            if t < range.from || t >= range.to || block_qs[i].is_missing() {
+                continue;
+            }
+            ts.push(t);
+            vals.push(block_vals[i]);
+            qs.push(block_qs[i]);
+        }
+    }
+
+    Ok(SeriesData {
+        series: series.id,
+        ts,
+        qs,
+        vals: T::into_value_vec(vals),
+    })
+}
+
+// A block touched by a query. `cold` holds the persisted block meta (if any),
+// `hot` is true if the block is (or was at listing time) held in the HotSet.
+// `version` pins an older stored version of the block, it is read instead of the current one.
+pub(crate) struct BlockRef<T: BlockWritable> {
+    pub id: BlockNumber,
+    pub cold: Option<BlockMeta<T>>,
+    // persisted partial integral, see BlockIntegral
+    pub integral: Option<BlockIntegral>,
+    pub hot: bool,
+    pub version: Option<String>,
+}
+
+// lists all blocks with data in [from, to), sorted by block id. with `as_of` the block versions
+// stored at that point in time are listed, the HotSet is left out.
+pub(crate) async fn blocks_in_range<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    from: u64,
+    to: u64,
+    as_of: Option<u64>,
+) -> Result<Vec<BlockRef<T>>, ApiError> {
+    let first = BlockNumber(helpers::get_block_id(series, from));
+    let last = BlockNumber(helpers::get_block_id(series, to - 1));
+
+    if let Some(as_of) = as_of {
+        let versions = state
+            .block_meta
+            .versions_as_of(series.id, first, last, as_of)
+            .await?;
+        return Ok(versions
+            .into_iter()
+            .map(|(id, key)| BlockRef {
+                id,
+                cold: None,
+                integral: None,
+                hot: false,
+                version: Some(key),
+            })
+            .collect());
+    }
+
+    let mut blocks = BTreeMap::new();
+    for (id, meta, integral) in state
+        .block_meta
+        .list_in_range::<T>(series.id, first, last)
+        .await?
+    {
+        blocks.insert(
+            id,
+            BlockRef {
+                id,
+                cold: Some(meta),
+                integral,
+                hot: false,
+                version: None,
+            },
+        );
+    }
+
+    let (live, flushing) = state.hot.get_live_blocks(series.id);
+    for id in live.into_iter().chain(flushing) {
+        if id < first || id > last {
+            continue;
+        }
+        blocks
+            .entry(id)
+            .or_insert(BlockRef {
+                id,
+                cold: None,
+                integral: None,
+                hot: true,
+                version: None,
+            })
+            .hot = true;
+    }
+
+    Ok(blocks.into_values().collect())
+}
+
+// loads a block, with the in memory data of the HotSet overlayed on top of the persisted data.
+// returns None if the block vanished in the meantime.
+pub(crate) async fn load_block<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    block_ref: &BlockRef<T>,
+) -> Result<Option<SizedBlock>, ApiError> {
+    if let Some(key) = &block_ref.version {
+        return persistence::read_block_version(&state.storage, key.clone())
+            .await
+            .map(Some);
+    }
+
+    let hot = if block_ref.hot {
+        state.hot.get_block(series.id, block_ref.id)
+    } else {
+        None
+    };
+
+    // if the hot block was flushed since listing, we fall back to the storage below
+    if hot.is_some() && block_ref.cold.is_none() {
+        return Ok(hot);
+    }
+
+    let cold = match persistence::read_block_from_storage(
+        &state.storage,
+        &state.block_meta,
+        series.id,
+        block_ref.id,
+    )
+    .await
+    {
+        Ok(b) => Some(b),
+        Err(ApiError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    Ok(match (cold, hot) {
+        (Some(mut cold), Some(hot)) => {
+            cold.overlay::<T>(&hot);
+            Some(cold)
+        }
+        (cold, hot) => cold.or(hot),
+    })
+}
@@ -0,0 +1,406 @@
+use std::collections::{BTreeMap, btree_map::Entry};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    api::{ApiError, as_internal_err},
+    meta::into_api_error,
+    query::{RangeQuery, SeriesTarget, blocks_in_range, load_block},
+};
+use vodnik_core::{
+    aggregate::{
+        AggResult, BucketRow, MultiAggResult, MultiBucketRow, QualityFilter, RangeAgg,
+        SeriesAggResult, SeriesBucketRow, add_slice_bucketed, bucket_index, fixed_bucket_bounds,
+        local_bucket_bounds, slots_in_range,
+    },
+    helpers,
+    meta::{BlockWritable, SeriesId, SeriesMeta, StorageType},
+};
+
+const MAX_BUCKETS: u64 = 100_000; // TODO: settings?
+
+#[derive(Debug, Deserialize)]
+pub struct AggregateQuery {
+    // [from, to) in ms after UNIX EPOCH
+    pub from: u64,
+    pub to: u64,
+    #[serde(default)]
+    pub quality: QualityFilter,
+    // see `RangeQuery`
+    #[serde(default)]
+    pub as_of: Option<u64>,
+}
+
+pub(crate) async fn aggregate(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Query(query): Query<AggregateQuery>,
+) -> Result<Json<AggResult>, ApiError> {
+    let range = RangeQuery {
+        from: query.from,
+        to: query.to,
+        as_of: query.as_of,
+    };
+    range.validate()?;
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+
+    Ok(Json(series_range_result(&state, &series, &query).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MultiAggregateQuery {
+    #[serde(flatten)]
+    pub target: SeriesTarget,
+    #[serde(flatten)]
+    pub range: AggregateQuery,
+}
+
+// aggregates a range over multiple series, per series and combined
+pub(crate) async fn multi_aggregate(
+    State(state): State<AppState>,
+    Json(query): Json<MultiAggregateQuery>,
+) -> Result<Json<MultiAggResult>, ApiError> {
+    RangeQuery {
+        from: query.range.from,
+        to: query.range.to,
+        as_of: query.range.as_of,
+    }
+    .validate()?;
+
+    let mut res: Option<MultiAggResult> = None;
+    for series in query.target.resolve(&state).await? {
+        let agg = series_range_result(&state, &series, &query.range).await?;
+        let row = SeriesAggResult {
+            series: series.id,
+            agg,
+        };
+
+        match &mut res {
+            Some(res) => {
+                res.agg.merge(&row.agg);
+                res.series.push(row);
+            }
+            None => {
+                res = Some(MultiAggResult {
+                    agg: row.agg.clone(),
+                    series: vec![row],
+                })
+            }
+        }
+    }
+
+    res.map(Json)
+        .ok_or_else(|| ApiError::NotFound("no series matches the selector".to_string()))
+}
+
+async fn series_range_result(
+    state: &AppState,
+    series: &SeriesMeta,
+    query: &AggregateQuery,
+) -> Result<AggResult, ApiError> {
+    match series.storage_type {
+        StorageType::Float32 => range_result::<f32>(state, series, query).await,
+        StorageType::Float64 => range_result::<f64>(state, series, query).await,
+        StorageType::Int32 => range_result::<i32>(state, series, query).await,
+        StorageType::Int64 => range_result::<i64>(state, series, query).await,
+        StorageType::UInt32 => range_result::<u32>(state, series, query).await,
+        StorageType::UInt64 => range_result::<u64>(state, series, query).await,
+        StorageType::Enumeration => range_result::<u8>(state, series, query).await,
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Utc,
+    // buckets follow the local wall clock of the series timezone
+    Local,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BucketQuery {
+    // [from, to) in ms after UNIX EPOCH
+    pub from: u64,
+    pub to: u64,
+    // bucket size, e.g. 15m, 1h, 1d
+    pub interval: String,
+    #[serde(default)]
+    pub align: Align,
+    #[serde(default)]
+    pub quality: QualityFilter,
+    // see `RangeQuery`
+    #[serde(default)]
+    pub as_of: Option<u64>,
+}
+
+impl BucketQuery {
+    // returns the interval in ms
+    pub fn validate(&self) -> Result<u64, ApiError> {
+        validate_interval(self.from, self.to, &self.interval)
+    }
+}
+
+// validates [from, to) and the bucket interval, returns the interval in ms
+pub(crate) fn validate_interval(from: u64, to: u64, interval: &str) -> Result<u64, ApiError> {
+    RangeQuery {
+        from,
+        to,
+        as_of: None,
+    }
+    .validate()?;
+
+    let interval = helpers::parse_interval(interval).map_err(ApiError::BadRequest)?;
+    if (to - from) / interval > MAX_BUCKETS {
+        return Err(ApiError::BadRequest(format!(
+            "too many buckets, at most {MAX_BUCKETS} buckets per query are allowed"
+        )));
+    }
+
+    Ok(interval)
+}
+
+pub(crate) async fn buckets(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Query(query): Query<BucketQuery>,
+) -> Result<Json<Vec<BucketRow>>, ApiError> {
+    let interval = query.validate()?;
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+
+    let rows = series_bucket_rows(&state, &series, &query, interval).await?;
+    Ok(Json(rows))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MultiBucketQuery {
+    #[serde(flatten)]
+    pub target: SeriesTarget,
+    #[serde(flatten)]
+    pub buckets: BucketQuery,
+}
+
+// buckets over multiple series, combined per bucket. with align=local buckets are matched by
+// their local wall clock start, e.g. 06:00 in Santiago and 06:00 in Sydney form one row.
+pub(crate) async fn multi_buckets(
+    State(state): State<AppState>,
+    Json(query): Json<MultiBucketQuery>,
+) -> Result<Json<Vec<MultiBucketRow>>, ApiError> {
+    let interval = query.buckets.validate()?;
+
+    let mut rows: BTreeMap<(Option<String>, u64), MultiBucketRow> = BTreeMap::new();
+    for series in query.target.resolve(&state).await? {
+        let id = series.id;
+        for row in series_bucket_rows(&state, &series, &query.buckets, interval).await? {
+            let key = match query.buckets.align {
+                Align::Utc => (None, row.start),
+                Align::Local => (row.local.clone(), 0),
+            };
+
+            match rows.entry(key) {
+                Entry::Vacant(e) => {
+                    e.insert(MultiBucketRow {
+                        start: (query.buckets.align == Align::Utc).then_some(row.start),
+                        local: row.local.clone(),
+                        agg: row.agg.clone(),
+                        series: vec![SeriesBucketRow { series: id, row }],
+                    });
+                }
+                Entry::Occupied(mut e) => {
+                    let entry = e.get_mut();
+                    entry.agg.merge(&row.agg);
+                    entry.series.push(SeriesBucketRow { series: id, row });
+                }
+            }
+        }
+    }
+
+    Ok(Json(rows.into_values().collect()))
+}
+
+async fn series_bucket_rows(
+    state: &AppState,
+    series: &SeriesMeta,
+    query: &BucketQuery,
+    interval: u64,
+) -> Result<Vec<BucketRow>, ApiError> {
+    let (bounds, labels) = bucket_bounds(series, query.from, query.to, interval, query.align)?;
+
+    let (b, l) = (bounds.as_slice(), labels.as_slice());
+    match series.storage_type {
+        StorageType::Float32 => bucket_rows::<f32>(state, series, b, l, query).await,
+        StorageType::Float64 => bucket_rows::<f64>(state, series, b, l, query).await,
+        StorageType::Int32 => bucket_rows::<i32>(state, series, b, l, query).await,
+        StorageType::Int64 => bucket_rows::<i64>(state, series, b, l, query).await,
+        StorageType::UInt32 => bucket_rows::<u32>(state, series, b, l, query).await,
+        StorageType::UInt64 => bucket_rows::<u64>(state, series, b, l, query).await,
+        StorageType::Enumeration => bucket_rows::<u8>(state, series, b, l, query).await,
+    }
+}
+
+// bucket boundaries together with the local start of each bucket (empty for UTC aligned buckets)
+pub(crate) fn bucket_bounds(
+    series: &SeriesMeta,
+    from: u64,
+    to: u64,
+    interval: u64,
+    align: Align,
+) -> Result<(Vec<u64>, Vec<String>), ApiError> {
+    Ok(match align {
+        Align::Utc => (fixed_bucket_bounds(from, to, interval), vec![]),
+        Align::Local => {
+            let tz = helpers::parse_timezone(&series.timezone).map_err(as_internal_err)?;
+            let (bounds, labels) = local_bucket_bounds(from, to, interval, &tz);
+            (
+                bounds,
+                labels.into_iter().map(helpers::format_local).collect(),
+            )
+        }
+    })
+}
+
+// like `bucket_bounds`, but a single bucket covering [from, to) if no interval is given
+pub(crate) fn range_or_bucket_bounds(
+    series: &SeriesMeta,
+    from: u64,
+    to: u64,
+    interval: Option<&str>,
+    align: Align,
+) -> Result<(Vec<u64>, Vec<String>), ApiError> {
+    match interval {
+        Some(i) => {
+            let interval = validate_interval(from, to, i)?;
+            bucket_bounds(series, from, to, interval, align)
+        }
+        None => {
+            RangeQuery {
+                from,
+                to,
+                as_of: None,
+            }
+            .validate()?;
+            Ok((vec![from, to], vec![]))
+        }
+    }
+}
+
+async fn range_result<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    query: &AggregateQuery,
+) -> Result<AggResult, ApiError> {
+    let (from, to, filter) = (query.from, query.to, query.quality);
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+    Ok(
+        aggregate_range::<T>(state, series, from, to, filter, query.as_of)
+            .await?
+            .result(slots_in_range(from, to, sample_ms)),
+    )
+}
+
+// `labels` holds the local start of each bucket, empty for UTC aligned buckets
+async fn bucket_rows<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    bounds: &[u64],
+    labels: &[String],
+    query: &BucketQuery,
+) -> Result<Vec<BucketRow>, ApiError> {
+    let (from, to, filter) = (query.from, query.to, query.quality);
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+    let aggs = aggregate_buckets::<T>(state, series, bounds, from, to, filter, query.as_of).await?;
+
+    Ok(aggs
+        .iter()
+        .enumerate()
+        .map(|(i, agg)| BucketRow {
+            start: bounds[i],
+            end: bounds[i + 1],
+            local: labels.get(i).cloned(),
+            // the first and last bucket may be cut by the query range
+            agg: agg.result(slots_in_range(
+                bounds[i].max(from),
+                bounds[i + 1].min(to),
+                sample_ms,
+            )),
+        })
+        .collect())
+}
+
+pub(crate) async fn aggregate_range<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    from: u64,
+    to: u64,
+    filter: QualityFilter,
+    as_of: Option<u64>,
+) -> Result<RangeAgg<T>, ApiError> {
+    let mut aggs =
+        aggregate_buckets::<T>(state, series, &[from, to], from, to, filter, as_of).await?;
+    Ok(aggs.pop().unwrap_or_else(|| RangeAgg::new(filter)))
+}
+
+// aggregates [from, to) into the buckets given by `bounds` (bucket i covers [bounds[i], bounds[i + 1])).
+// blocks fully inside the range and inside a single bucket are answered from their block meta,
+// if the meta stats match the quality filter. all other blocks (partially covered, spanning
+// buckets, still in the HotSet or with qualities the meta can't tell apart) are decoded.
+// with `as_of` the block versions stored at that point in time are aggregated.
+pub(crate) async fn aggregate_buckets<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    bounds: &[u64],
+    from: u64,
+    to: u64,
+    filter: QualityFilter,
+    as_of: Option<u64>,
+) -> Result<Vec<RangeAgg<T>>, ApiError> {
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+    let block_ms = helpers::duration(series.block_resolution, series.block_length.0);
+
+    let block_len = helpers::get_block_length(series);
+
+    let mut aggs = vec![RangeAgg::new(filter); bounds.len() - 1];
+    for block_ref in blocks_in_range::<T>(state, series, from, to, as_of).await? {
+        let bl_start = helpers::get_block_start_as_offset(series, block_ref.id.0);
+        let bl_end = bl_start + block_ms;
+
+        let b = bucket_index(bounds, bl_start.max(from));
+        let single =
+            from <= bl_start && bl_end <= to && bounds[b] <= bl_start && bl_end <= bounds[b + 1];
+
+        if single
+            && !block_ref.hot
+            && let Some(meta) = &block_ref.cold
+            && aggs[b].can_use_meta(meta)
+        {
+            aggs[b].add_block(bl_start, sample_ms, block_len, meta);
+            continue;
+        }
+
+        let Some(block) = load_block::<T>(state, series, &block_ref).await? else {
+            continue;
+        };
+
+        let (meta, vals, qs) = T::block_data(&block);
+        if single && aggs[b].can_use_meta(meta) {
+            aggs[b].add_block(bl_start, sample_ms, block_len, meta);
+        } else {
+            add_slice_bucketed(&mut aggs, bounds, bl_start, sample_ms, vals, qs, from..to);
+        }
+    }
+
+    Ok(aggs)
+}
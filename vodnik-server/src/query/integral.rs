@@ -0,0 +1,149 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    api::ApiError,
+    meta::into_api_error,
+    query::{
+        aggregate::{Align, range_or_bucket_bounds},
+        blocks_in_range, load_block,
+    },
+};
+use vodnik_core::{
+    aggregate::{
+        QualityFilter, bucket_index,
+        integral::{IntegralRow, Interpolation, TimeIntegral},
+    },
+    helpers,
+    meta::{BlockWritable, SeriesId, SeriesMeta, StorageType},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct IntegralQuery {
+    // [from, to) in ms after UNIX EPOCH
+    pub from: u64,
+    pub to: u64,
+    // bucket size, e.g. 1d. a single bucket covering [from, to) if not set
+    pub interval: Option<String>,
+    #[serde(default)]
+    pub align: Align,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub quality: QualityFilter,
+    // time unit of the integral, e.g. 1h for a flow rate in m³/h. defaults to 1s
+    pub unit: Option<String>,
+}
+
+pub(crate) async fn integral(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Query(query): Query<IntegralQuery>,
+) -> Result<Json<Vec<IntegralRow>>, ApiError> {
+    let unit_ms = match &query.unit {
+        Some(u) => helpers::parse_interval(u).map_err(ApiError::BadRequest)?,
+        None => 1000,
+    };
+
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+
+    let (bounds, labels) = range_or_bucket_bounds(
+        &series,
+        query.from,
+        query.to,
+        query.interval.as_deref(),
+        query.align,
+    )?;
+
+    let (b, q) = (bounds.as_slice(), &query);
+    let ints = match series.storage_type {
+        StorageType::Float32 => integrate_buckets::<f32>(&state, &series, b, q).await,
+        StorageType::Float64 => integrate_buckets::<f64>(&state, &series, b, q).await,
+        StorageType::Int32 => integrate_buckets::<i32>(&state, &series, b, q).await,
+        StorageType::Int64 => integrate_buckets::<i64>(&state, &series, b, q).await,
+        StorageType::UInt32 => integrate_buckets::<u32>(&state, &series, b, q).await,
+        StorageType::UInt64 => integrate_buckets::<u64>(&state, &series, b, q).await,
+        StorageType::Enumeration => integrate_buckets::<u8>(&state, &series, b, q).await,
+    }?;
+
+    let rows = ints
+        .iter()
+        .enumerate()
+        .map(|(i, int)| {
+            // the first and last bucket may be cut by the query range
+            let (start, end) = (bounds[i].max(query.from), bounds[i + 1].min(query.to));
+            IntegralRow {
+                start,
+                end,
+                local: labels.get(i).cloned(),
+                result: int.result(end, unit_ms),
+            }
+        })
+        .collect();
+
+    Ok(Json(rows))
+}
+
+// integrates [from, to) per bucket. buckets are integrated on their own, gaps across a bucket
+// boundary are not bridged. blocks fully inside a single bucket are added via their block meta
+// and stored partial integral, all others are decoded.
+async fn integrate_buckets<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    bounds: &[u64],
+    query: &IntegralQuery,
+) -> Result<Vec<TimeIntegral>, ApiError> {
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+    let block_ms = helpers::duration(series.block_resolution, series.block_length.0);
+    let (from, to) = (query.from, query.to);
+
+    let mut ints =
+        vec![TimeIntegral::new(query.interpolation, query.quality, sample_ms); bounds.len() - 1];
+    for block_ref in blocks_in_range::<T>(state, series, from, to, None).await? {
+        let bl_start = helpers::get_block_start_as_offset(series, block_ref.id.0);
+        let bl_end = bl_start + block_ms;
+
+        let mut b = bucket_index(bounds, bl_start.max(from));
+        let single =
+            from <= bl_start && bl_end <= to && bounds[b] <= bl_start && bl_end <= bounds[b + 1];
+
+        if single
+            && !block_ref.hot
+            && let Some(meta) = &block_ref.cold
+            && let Some(partial) = &block_ref.integral
+            && ints[b].can_use_meta(meta)
+        {
+            ints[b].add_block(bl_start, meta, partial);
+            continue;
+        }
+
+        let Some(block) = load_block::<T>(state, series, &block_ref).await? else {
+            continue;
+        };
+
+        let (_, vals, qs) = T::block_data(&block);
+        for i in 0..qs.len() {
+            let ts = bl_start + i as u64 * sample_ms;
+            if ts < from {
+                continue;
+            }
+            if ts >= to {
+                break;
+            }
+            while ts >= bounds[b + 1] {
+                b += 1;
+            }
+            ints[b].add_sample(ts, vals[i], qs[i]);
+        }
+    }
+
+    Ok(ints)
+}
@@ -0,0 +1,144 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    api::ApiError,
+    meta::into_api_error,
+    query::{
+        aggregate::{Align, range_or_bucket_bounds},
+        blocks_in_range, load_block,
+        point::{valid_at_or_after, valid_at_or_before},
+    },
+};
+use vodnik_core::{
+    aggregate::opc::{self, OpcAggregate, OpcConfig, OpcRow, RawPoint},
+    helpers,
+    meta::{BlockWritable, SeriesId, SeriesMeta, StorageType},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct OpcQuery {
+    // [from, to) in ms after UNIX EPOCH
+    pub from: u64,
+    pub to: u64,
+    // processing interval, e.g. 15m. a single interval covering [from, to) if not set
+    pub interval: Option<String>,
+    #[serde(default)]
+    pub align: Align,
+    pub aggregate: OpcAggregate,
+    // defaults to stepped for enumerations and linear interpolation for everything else
+    pub stepped: Option<bool>,
+    #[serde(default)]
+    pub treat_uncertain_as_bad: bool,
+    pub percent_data_good: Option<u8>,
+    pub percent_data_bad: Option<u8>,
+}
+
+impl OpcQuery {
+    fn config(&self, series: &SeriesMeta) -> Result<OpcConfig, ApiError> {
+        let default = OpcConfig::default();
+        let cfg = OpcConfig {
+            stepped: self
+                .stepped
+                .unwrap_or(matches!(series.storage_type, StorageType::Enumeration)),
+            treat_uncertain_as_bad: self.treat_uncertain_as_bad,
+            percent_data_good: self.percent_data_good.unwrap_or(default.percent_data_good),
+            percent_data_bad: self.percent_data_bad.unwrap_or(default.percent_data_bad),
+        };
+
+        if cfg.percent_data_good > 100 || cfg.percent_data_bad > 100 {
+            return Err(ApiError::BadRequest(
+                "'percent_data_good' and 'percent_data_bad' must be in [0, 100]".to_string(),
+            ));
+        }
+        Ok(cfg)
+    }
+}
+
+pub(crate) async fn opc_aggregate(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Query(query): Query<OpcQuery>,
+) -> Result<Json<Vec<OpcRow>>, ApiError> {
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+    let cfg = query.config(&series)?;
+
+    let (bounds, labels) = range_or_bucket_bounds(
+        &series,
+        query.from,
+        query.to,
+        query.interval.as_deref(),
+        query.align,
+    )?;
+
+    let (from, to) = (bounds[0], bounds[bounds.len() - 1]);
+    let points = match series.storage_type {
+        StorageType::Float32 => load_points::<f32>(&state, &series, from, to).await,
+        StorageType::Float64 => load_points::<f64>(&state, &series, from, to).await,
+        StorageType::Int32 => load_points::<i32>(&state, &series, from, to).await,
+        StorageType::Int64 => load_points::<i64>(&state, &series, from, to).await,
+        StorageType::UInt32 => load_points::<u32>(&state, &series, from, to).await,
+        StorageType::UInt64 => load_points::<u64>(&state, &series, from, to).await,
+        StorageType::Enumeration => load_points::<u8>(&state, &series, from, to).await,
+    }?;
+
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+    let rows = bounds
+        .windows(2)
+        .enumerate()
+        .map(|(i, w)| {
+            // the first and last interval may be cut by the query range
+            let (start, end) = (w[0].max(query.from), w[1].min(query.to));
+            OpcRow {
+                start,
+                end,
+                local: labels.get(i).cloned(),
+                value: opc::compute(query.aggregate, &cfg, &points, start, end, sample_ms),
+            }
+        })
+        .collect();
+
+    Ok(Json(rows))
+}
+
+// loads the non missing samples of [from, to), plus the closest valid sample on either side of
+// the range, so the interpolation at the range boundaries has bounding values to work with.
+async fn load_points<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    from: u64,
+    to: u64,
+) -> Result<Vec<RawPoint>, ApiError> {
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+
+    let mut points = vec![];
+    if from > 0
+        && let Some(p) = valid_at_or_before::<T>(state, series, from - 1).await?
+    {
+        points.push(p);
+    }
+
+    for block_ref in blocks_in_range::<T>(state, series, from, to, None).await? {
+        let Some(block) = load_block::<T>(state, series, &block_ref).await? else {
+            continue;
+        };
+
+        let bl_start = helpers::get_block_start_as_offset(series, block_ref.id.0);
+        let (_, vals, qs) = T::block_data(&block);
+        opc::push_points(&mut points, bl_start, sample_ms, vals, qs, from..to);
+    }
+
+    if let Some(p) = valid_at_or_after::<T>(state, series, to).await? {
+        points.push(p);
+    }
+
+    Ok(points)
+}
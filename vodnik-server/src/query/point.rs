@@ -0,0 +1,244 @@
+use std::ops::Range;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    api::ApiError,
+    meta::into_api_error,
+    query::{blocks_in_range, load_block},
+};
+use vodnik_core::{
+    aggregate::{AggSample, QualityFilter, ValueAt, integral::Interpolation, opc::RawPoint},
+    helpers,
+    meta::{BlockMeta, BlockNumber, BlockWritable, Quality, SeriesId, SeriesMeta, StorageType},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ValueQuery {
+    // ms after UNIX EPOCH
+    pub ts: u64,
+    // step: the last valid sample at or before ts (sample and hold),
+    // linear: interpolated between the samples around ts
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+pub(crate) async fn value_at(
+    State(state): State<AppState>,
+    Path(series_id): Path<SeriesId>,
+    Query(query): Query<ValueQuery>,
+) -> Result<Json<ValueAt>, ApiError> {
+    let series = state
+        .meta_store
+        .get(series_id)
+        .await
+        .map_err(into_api_error)?;
+
+    let (ts, mode) = (query.ts, query.interpolation);
+    let res = match series.storage_type {
+        StorageType::Float32 => value_at_ts::<f32>(&state, &series, ts, mode).await,
+        StorageType::Float64 => value_at_ts::<f64>(&state, &series, ts, mode).await,
+        StorageType::Int32 => value_at_ts::<i32>(&state, &series, ts, mode).await,
+        StorageType::Int64 => value_at_ts::<i64>(&state, &series, ts, mode).await,
+        StorageType::UInt32 => value_at_ts::<u32>(&state, &series, ts, mode).await,
+        StorageType::UInt64 => value_at_ts::<u64>(&state, &series, ts, mode).await,
+        StorageType::Enumeration => value_at_ts::<u8>(&state, &series, ts, mode).await,
+    }?;
+
+    Ok(Json(res))
+}
+
+async fn value_at_ts<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    ts: u64,
+    mode: Interpolation,
+) -> Result<ValueAt, ApiError> {
+    let prev = valid_at_or_before::<T>(state, series, ts).await?;
+
+    let next = match (mode, prev) {
+        (Interpolation::Linear, Some(p)) if p.ts < ts => {
+            valid_at_or_after::<T>(state, series, ts).await?
+        }
+        _ => None,
+    };
+
+    let (value, q) = match (prev, next) {
+        (None, _) => (None, Quality::MISSING),
+        (Some(p), Some(n)) => {
+            let v = p.value + (n.value - p.value) * (ts - p.ts) as f64 / (n.ts - p.ts) as f64;
+            let q = if p.q.is_good() && n.q.is_good() {
+                Quality::GOOD
+            } else {
+                Quality::UNCERTAIN
+            };
+            (Some(v), q)
+        }
+        // no sample after ts, the last one holds
+        (Some(p), None) => (Some(p.value), p.q),
+    };
+
+    let sample = |p: RawPoint| AggSample {
+        ts: p.ts,
+        value: p.value,
+        q: p.q,
+    };
+
+    Ok(ValueAt {
+        ts,
+        value,
+        q,
+        prev: prev.map(sample),
+        next: next.map(sample),
+    })
+}
+
+// last valid sample at or before `ts`. searches backwards over the blocks, stored blocks are
+// answered from their meta (lst_valid), only blocks held in the HotSet are decoded.
+pub(crate) async fn valid_at_or_before<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    ts: u64,
+) -> Result<Option<RawPoint>, ApiError> {
+    let mut block = BlockNumber(helpers::get_block_id(series, ts));
+    if let Some(p) = scan_block::<T>(state, series, block, 0..ts.saturating_add(1), true).await? {
+        return Ok(Some(p));
+    }
+
+    loop {
+        let cold = state
+            .block_meta
+            .last_valid_before::<T>(series.id, block)
+            .await?;
+        let (live, flushing) = state.hot.get_live_blocks(series.id);
+        let hot = live
+            .into_iter()
+            .chain(flushing)
+            .filter(|b| *b < block)
+            .max();
+
+        match (cold, hot) {
+            (None, None) => return Ok(None),
+            (Some((id, meta)), None) => return Ok(meta_point(series, id, &meta, true)),
+            (Some((id, meta)), Some(h)) if h < id => {
+                return Ok(meta_point(series, id, &meta, true));
+            }
+            // the hot block is the closer one (or overlays the stored one), decode it
+            (_, Some(h)) => {
+                if let Some(p) = scan_block::<T>(state, series, h, 0..u64::MAX, true).await? {
+                    return Ok(Some(p));
+                }
+                block = h;
+            }
+        }
+    }
+}
+
+// first valid sample at or after `ts`, see `valid_at_or_before`
+pub(crate) async fn valid_at_or_after<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    ts: u64,
+) -> Result<Option<RawPoint>, ApiError> {
+    let mut block = BlockNumber(helpers::get_block_id(series, ts));
+    if let Some(p) = scan_block::<T>(state, series, block, ts..u64::MAX, false).await? {
+        return Ok(Some(p));
+    }
+
+    loop {
+        let cold = state
+            .block_meta
+            .first_valid_after::<T>(series.id, block)
+            .await?;
+        let (live, flushing) = state.hot.get_live_blocks(series.id);
+        let hot = live
+            .into_iter()
+            .chain(flushing)
+            .filter(|b| *b > block)
+            .min();
+
+        match (cold, hot) {
+            (None, None) => return Ok(None),
+            (Some((id, meta)), None) => return Ok(meta_point(series, id, &meta, false)),
+            (Some((id, meta)), Some(h)) if h > id => {
+                return Ok(meta_point(series, id, &meta, false));
+            }
+            (_, Some(h)) => {
+                if let Some(p) = scan_block::<T>(state, series, h, 0..u64::MAX, false).await? {
+                    return Ok(Some(p));
+                }
+                block = h;
+            }
+        }
+    }
+}
+
+// the last (or first) valid sample of a block, taken from its meta
+fn meta_point<T: BlockWritable>(
+    series: &SeriesMeta,
+    id: BlockNumber,
+    meta: &BlockMeta<T>,
+    last: bool,
+) -> Option<RawPoint> {
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+    let bl_start = helpers::get_block_start_as_offset(series, id.0);
+
+    let (offset, v, q) = if last {
+        (meta.lst_valid_offset, meta.lst_valid, meta.lst_valid_q)
+    } else {
+        (meta.fst_valid_offset, meta.fst_valid, meta.fst_valid_q)
+    };
+
+    Some(RawPoint {
+        ts: bl_start + offset as u64 * sample_ms,
+        value: num_traits::cast(v)?,
+        q,
+    })
+}
+
+// decodes a block (stored and hot data merged) and returns its last (or first) valid sample
+// inside `range`
+async fn scan_block<T: BlockWritable>(
+    state: &AppState,
+    series: &SeriesMeta,
+    id: BlockNumber,
+    range: Range<u64>,
+    last: bool,
+) -> Result<Option<RawPoint>, ApiError> {
+    let sample_ms = helpers::duration(series.sample_resolution, series.sample_length.0);
+    let bl_start = helpers::get_block_start_as_offset(series, id.0);
+
+    let Some(block_ref) = blocks_in_range::<T>(state, series, bl_start, bl_start + 1, None)
+        .await?
+        .pop()
+    else {
+        return Ok(None);
+    };
+    let Some(block) = load_block::<T>(state, series, &block_ref).await? else {
+        return Ok(None);
+    };
+
+    let (_, vals, qs) = T::block_data(&block);
+    let to_point = |i: usize| -> Option<RawPoint> {
+        let ts = bl_start + i as u64 * sample_ms;
+        if !range.contains(&ts) || !QualityFilter::Valid.accepts(qs[i]) {
+            return None;
+        }
+        Some(RawPoint {
+            ts,
+            value: num_traits::cast(vals[i])?,
+            q: qs[i],
+        })
+    };
+
+    Ok(if last {
+        (0..qs.len()).rev().find_map(to_point)
+    } else {
+        (0..qs.len()).find_map(to_point)
+    })
+}
@@ -7,10 +7,10 @@ use std::{
 
 use tracing::info;
 use vodnik_core::{
-    meta::{BlockWritable, SeriesMeta, StorableNum, WriteBatch},
+    meta::{BlockWritable, CollisionPolicy, SeriesMeta, StorableNum, WriteBatch},
     wal::{
-        TAG_FLUSH, TAG_WRITE, TxId, WalEntry, WalEntryHeader, WalError, WalFrame, WalFrameIterator,
-        WalSync,
+        TAG_DELETE, TAG_FLUSH, TAG_OVERWRITE, TAG_WRITE, TxId, WalEntry, WalEntryHeader, WalError,
+        WalFrame, WalFrameIterator, WalSync,
     },
 };
 
@@ -134,7 +134,7 @@ pub fn find_wal_to_recover(wal_dir: PathBuf) -> Result<BTreeMap<TxId, WalFrame>,
 
             let header = WalEntryHeader::peek(frame.payload.as_mut_slice())?;
             match header.tag {
-                TAG_WRITE => {
+                TAG_WRITE | TAG_DELETE | TAG_OVERWRITE => {
                     _ = todo.insert(header.tx, frame);
                 }
                 TAG_FLUSH => {
@@ -240,6 +240,38 @@ async fn replay_entry<T: BlockWritable>(
             );
             crate::ingest::write_chunk(state, &batch, true).await?;
         }
+        WalEntry::Overwrite {
+            tx,
+            block,
+            ts,
+            vals,
+            qs,
+            ..
+        } => {
+            let series_meta = SeriesMeta {
+                collision: CollisionPolicy::LastWins,
+                ..series_meta.clone()
+            };
+            let batch = WriteBatch::new(
+                &series_meta,
+                block,
+                ts.as_slice(),
+                vals.as_slice(),
+                qs.as_slice(),
+                tx,
+            );
+            crate::edit::overwrite_in_block(state, &batch, true).await?;
+        }
+        WalEntry::Delete {
+            tx,
+            block,
+            from,
+            to,
+            ..
+        } => {
+            crate::edit::delete_in_block::<T>(state, series_meta, block, from..to, tx, None, true)
+                .await?;
+        }
         WalEntry::Flush { .. } => {
             unreachable!("");
         }
@@ -252,8 +284,9 @@ pub async fn force_flush(state: &AppState) -> anyhow::Result<()> {
     state.hot.take_all_blocks(&mut blocks);
 
     let len = blocks.len();
-    for (s, _, bn, sb) in blocks {
-        persistence::flush_block(&state.storage, &state.block_meta, s, bn, &sb).await?;
+    for (s, tx, bn, sb, ops) in blocks {
+        let ops: Vec<&str> = ops.iter().map(String::as_str).collect();
+        persistence::flush_block(&state.storage, &state.block_meta, s, bn, &sb, tx, &ops).await?;
     }
 
     info!("force flushed {len} blocks");